@@ -0,0 +1,71 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::{BackendError, DecodedImage, ImageBackend};
+
+const PROGRAM: &str = "heif-convert";
+
+/// Decodes by running the `heif-convert` tool from libheif into a temporary PNG,
+/// which is then read by another backend.
+pub struct HeifConvertBackend {
+    program: PathBuf,
+    reader: Box<dyn ImageBackend>,
+}
+
+impl HeifConvertBackend {
+    /// Returns `None` if `heif-convert` is not installed.
+    pub fn new(reader: Box<dyn ImageBackend>) -> Option<Self> {
+        Some(Self {
+            program: find_program()?,
+            reader,
+        })
+    }
+}
+
+/// Looks up `heif-convert` in `PATH`.
+pub fn find_program() -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(PROGRAM))
+        .find(|program| program.is_file())
+}
+
+impl ImageBackend for HeifConvertBackend {
+    fn name(&self) -> &'static str {
+        PROGRAM
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Box<dyn DecodedImage>, BackendError> {
+        let temp_error = |e: std::io::Error| BackendError::Decode(e.to_string());
+
+        let mut input = tempfile::Builder::new()
+            .suffix(".heic")
+            .tempfile()
+            .map_err(temp_error)?;
+        input.write_all(data).map_err(temp_error)?;
+        let output = tempfile::Builder::new()
+            .suffix(".png")
+            .tempfile()
+            .map_err(temp_error)?;
+
+        let result = Command::new(&self.program)
+            .arg(input.path())
+            .arg(output.path())
+            .output()
+            .map_err(temp_error)?;
+        if !result.status.success() {
+            return Err(BackendError::Decode(format!(
+                "{} exited with {}: {}",
+                PROGRAM,
+                result.status,
+                String::from_utf8_lossy(&result.stderr).trim()
+            )));
+        }
+
+        let png = fs::read(output.path()).map_err(temp_error)?;
+        self.reader.decode(&png)
+    }
+}
@@ -0,0 +1,95 @@
+use magick_rust::{magick_wand_genesis, FilterType, MagickWand, ResourceType};
+use std::sync::Once;
+use tracing::{info, warn};
+
+use super::{BackendError, DecodedImage, ImageBackend, ResourceLimits};
+
+static GENESIS: Once = Once::new();
+
+/// Initializes ImageMagick, which has to happen before any other call into it.
+fn genesis() {
+    GENESIS.call_once(magick_wand_genesis);
+}
+
+/// Sets ImageMagick's process wide resource limits.
+pub fn apply_resource_limits(limits: &ResourceLimits) {
+    genesis();
+    let resources = [
+        ("memory", ResourceType::Memory, limits.memory),
+        // Memory mapped pixel caches count against the memory budget as well
+        ("map", ResourceType::Map, limits.memory),
+        ("disk", ResourceType::Disk, limits.disk),
+        ("thread", ResourceType::Thread, limits.threads),
+    ];
+    for (name, resource, limit) in resources {
+        let Some(limit) = limit else {
+            continue;
+        };
+        match MagickWand::set_resource_limit(resource, limit) {
+            Ok(()) => info!("Limited ImageMagick {} to {}", name, limit),
+            Err(e) => warn!("Unable to limit ImageMagick {}: {}", name, e),
+        }
+    }
+}
+
+/// Decodes through ImageMagick and whatever delegates it was built with.
+pub struct ImageMagickBackend;
+
+impl ImageMagickBackend {
+    pub fn new() -> Self {
+        genesis();
+        Self
+    }
+}
+
+impl ImageBackend for ImageMagickBackend {
+    fn name(&self) -> &'static str {
+        "ImageMagick"
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Box<dyn DecodedImage>, BackendError> {
+        let wand = MagickWand::new();
+        wand.read_image_blob(data).map_err(|e| {
+            let reason = e.to_string();
+            if reason.contains("delegate") {
+                BackendError::Unsupported(reason)
+            } else {
+                BackendError::Decode(reason)
+            }
+        })?;
+
+        Ok(Box::new(MagickImage { wand }))
+    }
+}
+
+struct MagickImage {
+    wand: MagickWand,
+}
+
+impl DecodedImage for MagickImage {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.wand.get_image_width(), self.wand.get_image_height())
+    }
+
+    fn resize(&mut self, width: usize, height: usize) -> Result<(), BackendError> {
+        self.wand
+            .resize_image(width, height, FilterType::Lanczos)
+            .map_err(|e| BackendError::Encode(e.to_string()))
+    }
+
+    fn encode_jpeg(&self, quality: u8) -> Result<Vec<u8>, BackendError> {
+        let encode = || {
+            self.wand.set_image_format("jpg")?;
+            self.wand.set_image_compression_quality(quality.into())?;
+            self.wand.write_image_blob("jpg")
+        };
+        encode().map_err(|e| BackendError::Encode(e.to_string()))
+    }
+
+    fn rgb_pixels(&self) -> Result<Vec<u8>, BackendError> {
+        let (width, height) = self.dimensions();
+        self.wand
+            .export_image_pixels(0, 0, width, height, "RGB")
+            .ok_or_else(|| BackendError::Decode("unable to export the pixels".to_string()))
+    }
+}
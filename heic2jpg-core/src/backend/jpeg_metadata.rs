@@ -0,0 +1,265 @@
+//! Adds EXIF and ICC metadata to JPEGs written by encoders that do not carry it over themselves.
+
+use tracing::warn;
+
+/// Largest payload of a single JPEG segment, its length field counts itself as well.
+const MAX_SEGMENT_PAYLOAD: usize = u16::MAX as usize - 2;
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP2: u8 = 0xE2;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Metadata of a decoded image that belongs into the JPEG encoded from it.
+#[derive(Debug, Clone, Default)]
+pub(super) struct JpegMetadata {
+    /// EXIF data starting with the TIFF header.
+    pub exif: Option<Vec<u8>>,
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl JpegMetadata {
+    /// Returns `jpeg` with the metadata inserted after the JFIF segment.
+    ///
+    /// Metadata that does not fit into JPEG segments is left out.
+    pub fn insert_into(&self, jpeg: Vec<u8>) -> Vec<u8> {
+        if self.exif.is_none() && self.icc_profile.is_none() {
+            return jpeg;
+        }
+        let Some(position) = metadata_position(&jpeg) else {
+            warn!("Not a JPEG, unable to add the metadata");
+            return jpeg;
+        };
+
+        let mut segments = Vec::new();
+        if let Some(exif) = &self.exif {
+            if EXIF_HEADER.len() + exif.len() <= MAX_SEGMENT_PAYLOAD {
+                push_segment(&mut segments, APP1, &[EXIF_HEADER, exif]);
+            } else {
+                warn!("EXIF data of {} bytes is too large to keep", exif.len());
+            }
+        }
+        if let Some(profile) = &self.icc_profile {
+            // Profiles larger than a segment are split up, numbered from 1
+            let chunks: Vec<_> = profile
+                .chunks(MAX_SEGMENT_PAYLOAD - ICC_HEADER.len() - 2)
+                .collect();
+            match u8::try_from(chunks.len()) {
+                Ok(count) => {
+                    for (number, chunk) in (1..=count).zip(chunks) {
+                        push_segment(&mut segments, APP2, &[ICC_HEADER, &[number, count], chunk]);
+                    }
+                }
+                Err(_) => warn!(
+                    "ICC profile of {} bytes is too large to keep",
+                    profile.len()
+                ),
+            }
+        }
+
+        let mut with_metadata = Vec::with_capacity(jpeg.len() + segments.len());
+        with_metadata.extend_from_slice(&jpeg[..position]);
+        with_metadata.extend_from_slice(&segments);
+        with_metadata.extend_from_slice(&jpeg[position..]);
+        with_metadata
+    }
+}
+
+/// Turns an `Exif` item of a HEIF file into EXIF data for a JPEG.
+///
+/// The decoder already applied the rotation and mirroring of the image, so the orientation is
+/// reset as ImageMagick does. Keeping it would make viewers rotate the image a second time.
+/// Returns `None` if the item does not hold EXIF data.
+pub(super) fn exif_from_heif(item: &[u8]) -> Option<Vec<u8>> {
+    // The item starts with the offset of the TIFF header, which usually skips `Exif\0\0`
+    let offset = u32::from_be_bytes(item.get(..4)?.try_into().ok()?) as usize;
+    let mut tiff = item.get(offset.checked_add(4)?..)?.to_vec();
+    reset_orientation(&mut tiff)?;
+    Some(tiff)
+}
+
+/// Sets the orientation in the first IFD of `tiff` to "top left", if it has one.
+///
+/// Returns `None` if `tiff` does not start with a valid TIFF header.
+fn reset_orientation(tiff: &mut [u8]) -> Option<()> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |data: &[u8], at: usize| -> Option<u16> {
+        let bytes = data.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let first_ifd = tiff.get(4..8)?.try_into().ok()?;
+    let first_ifd = if big_endian {
+        u32::from_be_bytes(first_ifd)
+    } else {
+        u32::from_le_bytes(first_ifd)
+    } as usize;
+
+    let entries = read_u16(tiff, first_ifd)?;
+    for index in 0..usize::from(entries) {
+        let entry = first_ifd + 2 + index * 12;
+        if read_u16(tiff, entry)? == ORIENTATION_TAG {
+            // A single SHORT, stored at the start of the value field
+            let top_left = if big_endian {
+                1u16.to_be_bytes()
+            } else {
+                1u16.to_le_bytes()
+            };
+            tiff.get_mut(entry + 8..entry + 10)?
+                .copy_from_slice(&top_left);
+        }
+    }
+    Some(())
+}
+
+/// Where the metadata segments go: after the start of image marker and the JFIF segment.
+fn metadata_position(jpeg: &[u8]) -> Option<usize> {
+    if jpeg.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    if jpeg.get(2..4) != Some(&[0xFF, APP0]) {
+        return Some(2);
+    }
+    let length = u16::from_be_bytes(jpeg.get(4..6)?.try_into().ok()?);
+    let position = 4 + usize::from(length);
+    (position <= jpeg.len()).then_some(position)
+}
+
+fn push_segment(segments: &mut Vec<u8>, marker: u8, parts: &[&[u8]]) {
+    let length = 2 + parts.iter().map(|part| part.len()).sum::<usize>();
+    segments.extend_from_slice(&[0xFF, marker]);
+    segments.extend_from_slice(&(length as u16).to_be_bytes());
+    for part in parts {
+        segments.extend_from_slice(part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little endian TIFF header with a single IFD entry for the orientation.
+    fn tiff_with_orientation(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&ORIENTATION_TAG.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff
+    }
+
+    #[test]
+    fn exif_from_heif_skips_the_prefix_and_resets_the_orientation() {
+        let mut item = 6u32.to_be_bytes().to_vec();
+        item.extend_from_slice(EXIF_HEADER);
+        item.extend_from_slice(&tiff_with_orientation(6));
+
+        assert_eq!(exif_from_heif(&item), Some(tiff_with_orientation(1)));
+    }
+
+    #[test]
+    fn exif_from_heif_handles_big_endian_data() {
+        let mut item = 0u32.to_be_bytes().to_vec();
+        item.extend_from_slice(b"MM\0*");
+        item.extend_from_slice(&8u32.to_be_bytes());
+        item.extend_from_slice(&1u16.to_be_bytes());
+        item.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+        item.extend_from_slice(&3u16.to_be_bytes());
+        item.extend_from_slice(&1u32.to_be_bytes());
+        item.extend_from_slice(&8u16.to_be_bytes());
+        item.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+        let exif = exif_from_heif(&item).unwrap();
+        assert_eq!(exif[18..20], 1u16.to_be_bytes());
+    }
+
+    #[test]
+    fn exif_from_heif_rejects_invalid_data() {
+        assert_eq!(exif_from_heif(&[0, 0]), None);
+        assert_eq!(exif_from_heif(&[0, 0, 0, 100, b'I', b'I']), None);
+        assert_eq!(exif_from_heif(b"\0\0\0\0XX*\0\x08\0\0\0"), None);
+        // The IFD offset points past the end
+        assert_eq!(exif_from_heif(b"\0\0\0\0II*\0\xff\0\0\0"), None);
+    }
+
+    #[test]
+    fn insert_into_places_segments_after_jfif() {
+        let jfif = [0xFF, APP0, 0, 4, b'J', b'F'];
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&jfif);
+        jpeg.extend_from_slice(&[0xFF, 0xDB, 0xFF, 0xD9]);
+        let metadata = JpegMetadata {
+            exif: Some(b"II*\0".to_vec()),
+            icc_profile: Some(vec![1, 2, 3]),
+        };
+
+        let mut expected = vec![0xFF, 0xD8];
+        expected.extend_from_slice(&jfif);
+        expected.extend_from_slice(&[0xFF, APP1, 0, 12]);
+        expected.extend_from_slice(b"Exif\0\0II*\0");
+        expected.extend_from_slice(&[0xFF, APP2, 0, 19]);
+        expected.extend_from_slice(b"ICC_PROFILE\0");
+        expected.extend_from_slice(&[1, 1, 1, 2, 3]);
+        expected.extend_from_slice(&[0xFF, 0xDB, 0xFF, 0xD9]);
+        assert_eq!(metadata.insert_into(jpeg), expected);
+    }
+
+    #[test]
+    fn insert_into_splits_large_icc_profiles() {
+        let jpeg = vec![0xFF, 0xD8, 0xFF, 0xD9];
+        let metadata = JpegMetadata {
+            exif: None,
+            icc_profile: Some(vec![7; 100_000]),
+        };
+
+        let written = metadata.insert_into(jpeg);
+        let chunk = MAX_SEGMENT_PAYLOAD - ICC_HEADER.len() - 2;
+        assert_eq!(written[2..4], [0xFF, APP2]);
+        assert_eq!(written[4..6], (u16::MAX).to_be_bytes());
+        assert_eq!(written[18..20], [1, 2]);
+        let second = 4 + 2 + ICC_HEADER.len() + 2 + chunk;
+        assert_eq!(written[second..second + 2], [0xFF, APP2]);
+        assert_eq!(written[second + 16..second + 18], [2, 2]);
+        assert_eq!(written.len(), 4 + 2 * (4 + ICC_HEADER.len() + 2) + 100_000);
+    }
+
+    #[test]
+    fn jpeg_with_metadata_still_decodes() {
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+            .encode_image(&image::RgbImage::new(8, 8))
+            .unwrap();
+        let metadata = JpegMetadata {
+            exif: Some(tiff_with_orientation(1)),
+            icc_profile: Some(vec![0; 1000]),
+        };
+
+        let decoded = image::load_from_memory(&metadata.insert_into(jpeg)).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (8, 8));
+    }
+
+    #[test]
+    fn insert_into_leaves_other_data_alone() {
+        let metadata = JpegMetadata {
+            exif: Some(b"II*\0".to_vec()),
+            icc_profile: None,
+        };
+        assert_eq!(metadata.insert_into(b"not a jpeg".to_vec()), b"not a jpeg");
+        assert_eq!(
+            JpegMetadata::default().insert_into(vec![0xFF, 0xD8]),
+            [0xFF, 0xD8]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use libheif_rs::{
+    check_file_type, ColorSpace, FileTypeResult, HeifContext, HeifError, HeifErrorCode,
+    ImageHandle, LibHeif, RgbChroma,
+};
+
+use super::jpeg_metadata::{self, JpegMetadata};
+use super::{BackendError, DecodedImage, ImageBackend};
+
+/// Decodes with libheif directly and encodes with a pure Rust JPEG encoder.
+///
+/// Like ImageMagick, the EXIF data and ICC profile of the input are kept.
+#[derive(Default)]
+pub struct LibheifBackend {
+    lib: LibHeif,
+}
+
+impl ImageBackend for LibheifBackend {
+    fn name(&self) -> &'static str {
+        "libheif"
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Box<dyn DecodedImage>, BackendError> {
+        // Anything that is not HEIF (e.g. PNGs from external decoders) goes to the image crate
+        if check_file_type(data) == FileTypeResult::No {
+            let image = image::load_from_memory(data)
+                .map_err(|e| BackendError::Unsupported(e.to_string()))?;
+            return Ok(Box::new(RgbDecodedImage {
+                image: image.to_rgb8(),
+                metadata: JpegMetadata::default(),
+            }));
+        }
+
+        let context = HeifContext::read_from_bytes(data).map_err(heif_error)?;
+        let handle = context.primary_image_handle().map_err(heif_error)?;
+        // Decoding also applies the rotation and mirroring stored in the file
+        let decoded = self
+            .lib
+            .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+            .map_err(heif_error)?;
+
+        let plane = decoded
+            .planes()
+            .interleaved
+            .ok_or_else(|| BackendError::Decode("no interleaved RGB plane".to_string()))?;
+        let (width, height) = (plane.width as usize, plane.height as usize);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for row in plane.data.chunks(plane.stride).take(height) {
+            pixels.extend_from_slice(&row[..width * 3]);
+        }
+        let image = RgbImage::from_raw(plane.width, plane.height, pixels)
+            .ok_or_else(|| BackendError::Decode("pixel data too short".to_string()))?;
+
+        Ok(Box::new(RgbDecodedImage {
+            image,
+            metadata: read_metadata(&handle),
+        }))
+    }
+}
+
+/// Reads the EXIF data and ICC profile, which the JPEG encoder does not write by itself.
+fn read_metadata(handle: &ImageHandle) -> JpegMetadata {
+    let exif = handle
+        .all_metadata()
+        .into_iter()
+        .find(|metadata| metadata.item_type.0 == *b"Exif")
+        .and_then(|metadata| jpeg_metadata::exif_from_heif(&metadata.raw_data));
+    JpegMetadata {
+        exif,
+        icc_profile: handle.color_profile_raw().map(|profile| profile.data),
+    }
+}
+
+fn heif_error(error: HeifError) -> BackendError {
+    match error.code {
+        HeifErrorCode::UnsupportedFileType | HeifErrorCode::UnsupportedFeature => {
+            BackendError::Unsupported(error.to_string())
+        }
+        _ => BackendError::Decode(error.to_string()),
+    }
+}
+
+struct RgbDecodedImage {
+    image: RgbImage,
+    metadata: JpegMetadata,
+}
+
+impl DecodedImage for RgbDecodedImage {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.image.width() as usize, self.image.height() as usize)
+    }
+
+    fn resize(&mut self, width: usize, height: usize) -> Result<(), BackendError> {
+        self.image = imageops::resize(
+            &self.image,
+            width as u32,
+            height as u32,
+            FilterType::Lanczos3,
+        );
+        Ok(())
+    }
+
+    fn encode_jpeg(&self, quality: u8) -> Result<Vec<u8>, BackendError> {
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, quality)
+            .encode_image(&self.image)
+            .map_err(|e| BackendError::Encode(e.to_string()))?;
+        Ok(self.metadata.insert_into(data))
+    }
+
+    fn rgb_pixels(&self) -> Result<Vec<u8>, BackendError> {
+        Ok(self.image.as_raw().clone())
+    }
+}
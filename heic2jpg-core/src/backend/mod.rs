@@ -0,0 +1,196 @@
+//! Decoders and encoders the conversion can run on.
+//!
+//! Every backend sits behind its own cargo feature, which one is used is picked at runtime.
+
+mod heif_convert;
+#[cfg(feature = "imagemagick")]
+mod imagemagick;
+#[cfg(feature = "libheif")]
+mod jpeg_metadata;
+#[cfg(feature = "libheif")]
+mod libheif;
+
+use std::fmt;
+
+#[cfg(not(any(feature = "imagemagick", feature = "libheif")))]
+compile_error!("At least one of the `imagemagick` and `libheif` features has to be enabled");
+
+/// Decodes images into something that can be resized and encoded as JPEG.
+pub trait ImageBackend: Send + Sync {
+    /// Human readable name, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Decodes an encoded image, usually a HEIC file.
+    ///
+    /// Backends also have to read PNG, which is what external decoders hand back.
+    fn decode(&self, data: &[u8]) -> Result<Box<dyn DecodedImage>, BackendError>;
+}
+
+/// An image decoded by an [`ImageBackend`].
+pub trait DecodedImage {
+    /// Width and height in pixels.
+    fn dimensions(&self) -> (usize, usize);
+
+    fn resize(&mut self, width: usize, height: usize) -> Result<(), BackendError>;
+
+    fn encode_jpeg(&self, quality: u8) -> Result<Vec<u8>, BackendError>;
+
+    /// Pixels as interleaved 8 bit RGB, row by row.
+    fn rgb_pixels(&self) -> Result<Vec<u8>, BackendError>;
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    /// The data is not in a format (or uses a codec) the backend can decode.
+    Unsupported(String),
+    Decode(String),
+    Encode(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Unsupported(reason) => write!(f, "unsupported: {}", reason),
+            BackendError::Decode(reason) => write!(f, "decoding failed: {}", reason),
+            BackendError::Encode(reason) => write!(f, "encoding failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Upper bounds for what a backend may use while decoding. `None` keeps the library default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceLimits {
+    /// Pixel cache kept in memory, in bytes.
+    pub memory: Option<u64>,
+    /// Pixel cache spilled to disk, in bytes.
+    pub disk: Option<u64>,
+    pub threads: Option<u64>,
+}
+
+/// Applies `limits` to every backend that supports them. Has to run before the first decode.
+pub fn apply_resource_limits(limits: &ResourceLimits) {
+    #[cfg(feature = "imagemagick")]
+    imagemagick::apply_resource_limits(limits);
+    #[cfg(not(feature = "imagemagick"))]
+    let _ = limits;
+}
+
+/// The backends compiled into this build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    #[cfg(feature = "imagemagick")]
+    ImageMagick,
+    #[cfg(feature = "libheif")]
+    Libheif,
+}
+
+impl BackendKind {
+    /// All backends available in this build, the default one first.
+    pub const ALL: &'static [BackendKind] = &[
+        #[cfg(feature = "imagemagick")]
+        BackendKind::ImageMagick,
+        #[cfg(feature = "libheif")]
+        BackendKind::Libheif,
+    ];
+
+    /// Stable identifier, used in the settings.
+    pub fn id(self) -> &'static str {
+        match self {
+            #[cfg(feature = "imagemagick")]
+            BackendKind::ImageMagick => "imagemagick",
+            #[cfg(feature = "libheif")]
+            BackendKind::Libheif => "libheif",
+        }
+    }
+
+    /// Name shown in the preferences.
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "imagemagick")]
+            BackendKind::ImageMagick => "ImageMagick",
+            #[cfg(feature = "libheif")]
+            BackendKind::Libheif => "libheif",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.id() == id)
+    }
+
+    pub fn create(self) -> Box<dyn ImageBackend> {
+        match self {
+            #[cfg(feature = "imagemagick")]
+            BackendKind::ImageMagick => Box::new(imagemagick::ImageMagickBackend::new()),
+            #[cfg(feature = "libheif")]
+            BackendKind::Libheif => Box::new(libheif::LibheifBackend::default()),
+        }
+    }
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        Self::ALL[0]
+    }
+}
+
+/// Decoder to retry with when the main backend cannot read a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackDecoder {
+    Backend(BackendKind),
+    /// The `heif-convert` command line tool from libheif, if it is installed.
+    HeifConvert,
+}
+
+impl FallbackDecoder {
+    /// All fallbacks that can be configured in this build.
+    pub fn all() -> Vec<FallbackDecoder> {
+        let mut all: Vec<_> = BackendKind::ALL
+            .iter()
+            .map(|kind| FallbackDecoder::Backend(*kind))
+            .collect();
+        all.push(FallbackDecoder::HeifConvert);
+        all
+    }
+
+    /// Stable identifier, used in the settings.
+    pub fn id(self) -> &'static str {
+        match self {
+            FallbackDecoder::Backend(kind) => kind.id(),
+            FallbackDecoder::HeifConvert => "heif-convert",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FallbackDecoder::Backend(kind) => kind.name(),
+            FallbackDecoder::HeifConvert => "heif-convert",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::all().into_iter().find(|fallback| fallback.id() == id)
+    }
+
+    /// The fallback used unless configured otherwise, the same as in the app's settings.
+    ///
+    /// libheif, or none in builds without it.
+    pub fn default_fallback() -> Option<Self> {
+        Self::from_id("libheif")
+    }
+
+    /// Creates the fallback for a conversion running on `primary`.
+    ///
+    /// Returns `None` if it would be the same as `primary` or is not installed.
+    pub fn create(self, primary: BackendKind) -> Option<Box<dyn ImageBackend>> {
+        match self {
+            FallbackDecoder::Backend(kind) if kind == primary => None,
+            FallbackDecoder::Backend(kind) => Some(kind.create()),
+            FallbackDecoder::HeifConvert => {
+                let backend = heif_convert::HeifConvertBackend::new(primary.create())?;
+                Some(Box::new(backend))
+            }
+        }
+    }
+}
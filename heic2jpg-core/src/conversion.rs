@@ -0,0 +1,1567 @@
+//! Converting a folder of HEIC files, reporting progress as [`Event`]s.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::backend::{
+    BackendError, BackendKind, DecodedImage, FallbackDecoder, ImageBackend, ResourceLimits,
+};
+use crate::decode_helper;
+use crate::discovery::{Discovered, Discovery, DiscoveryOptions};
+use crate::report::{ConversionReport, FileStatus, ReportEntry};
+use crate::verification::{self, Verification};
+
+/// Lowest quality the target size search tries before it starts downscaling.
+const MIN_QUALITY_BEFORE_DOWNSCALE: u8 = 60;
+/// Factor applied to both edges on every downscaling step.
+const DOWNSCALE_STEP: f64 = 0.85;
+/// Images are never downscaled below this length of their longest edge.
+const MIN_DOWNSCALE_EDGE: usize = 320;
+/// Minimum time between two `FilesDiscovered` updates while scanning.
+const DISCOVERY_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the JPEG quality of the written files is chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualityMode {
+    /// Encode every image with the same quality (1-100).
+    Fixed(u8),
+    /// Use the highest quality at which the output fits into `max_bytes`.
+    ///
+    /// If `allow_downscale` is set the image gets scaled down step by step
+    /// instead of dropping below a reasonable quality.
+    TargetSize {
+        max_bytes: u64,
+        allow_downscale: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionOptions {
+    pub quality: QualityMode,
+    pub discovery: DiscoveryOptions,
+    pub backend: BackendKind,
+    /// Decoder to retry with when `backend` fails to decode a file.
+    pub fallback: Option<FallbackDecoder>,
+    /// Files taking longer than this to convert are marked as failed.
+    ///
+    /// Implies `isolate`, a decode cannot be interrupted but the helper process can be killed.
+    pub timeout: Option<Duration>,
+    /// Convert every file in a helper process, so a crashing decoder only fails that file.
+    pub isolate: bool,
+    /// Lock the helper process down with Landlock and seccomp.
+    pub sandbox: bool,
+    /// Re-read every written file and check it against the encoded image.
+    pub verify: Option<Verification>,
+    /// Flush every written file to disk before moving it into place.
+    pub sync: bool,
+    /// Limits applied by helper processes before decoding.
+    ///
+    /// Conversions in this process use whatever [`apply_resource_limits`] was called with.
+    ///
+    /// [`apply_resource_limits`]: crate::backend::apply_resource_limits
+    pub resource_limits: ResourceLimits,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            quality: QualityMode::Fixed(92),
+            discovery: DiscoveryOptions::default(),
+            backend: BackendKind::default(),
+            fallback: FallbackDecoder::default_fallback(),
+            timeout: None,
+            isolate: false,
+            sandbox: true,
+            verify: None,
+            sync: false,
+            resource_limits: ResourceLimits::default(),
+        }
+    }
+}
+
+/// Brands in the `ftyp` box of files we know how to decode.
+///
+/// Files are accepted if one of these is the major brand or one of the compatible brands, so
+/// e.g. AVIF files or files with a newer major brand that list `mif1` get through as well.
+const HEIF_BRANDS: &[&[u8; 4]] = &[
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"mif2", b"msf1",
+];
+
+/// Why converting a file failed. Every variant carries the affected path.
+#[derive(Debug, Clone)]
+pub enum ConversionError {
+    /// The input file could not be opened or read.
+    UnreadableInput { path: PathBuf, reason: String },
+    /// The input is not a HEIF image or needs a codec that is not available.
+    UnsupportedFormat { path: PathBuf, reason: String },
+    /// The input looked like a HEIF image but decoding it failed.
+    DecodeFailed { path: PathBuf, reason: String },
+    /// The decoded image could not be encoded as JPEG.
+    EncodeFailed { path: PathBuf, reason: String },
+    /// Access to the input or output path was denied.
+    PermissionDenied { path: PathBuf },
+    /// The output could not be written because the disk is full.
+    DiskFull { path: PathBuf },
+    /// Any other I/O error while writing the output.
+    Io { path: PathBuf, reason: String },
+    /// The written file does not decode or differs from the encoded image.
+    VerificationFailed { path: PathBuf, reason: String },
+    /// Converting the file took longer than the configured timeout.
+    TimedOut { path: PathBuf, timeout: Duration },
+    /// An include or exclude pattern for the input folder at `path` is not a valid glob.
+    InvalidPattern {
+        path: PathBuf,
+        pattern: String,
+        reason: String,
+    },
+}
+
+impl ConversionError {
+    /// The file the error refers to.
+    pub fn path(&self) -> &Path {
+        match self {
+            ConversionError::UnreadableInput { path, .. }
+            | ConversionError::UnsupportedFormat { path, .. }
+            | ConversionError::DecodeFailed { path, .. }
+            | ConversionError::EncodeFailed { path, .. }
+            | ConversionError::PermissionDenied { path }
+            | ConversionError::DiskFull { path }
+            | ConversionError::Io { path, .. }
+            | ConversionError::VerificationFailed { path, .. }
+            | ConversionError::TimedOut { path, .. }
+            | ConversionError::InvalidPattern { path, .. } => path,
+        }
+    }
+
+    /// Stable identifier of the kind of error, used in machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConversionError::UnreadableInput { .. } => "unreadable",
+            ConversionError::UnsupportedFormat { .. } => "unsupported",
+            ConversionError::DecodeFailed { .. } => "decode",
+            ConversionError::EncodeFailed { .. } => "encode",
+            ConversionError::PermissionDenied { .. } => "permission",
+            ConversionError::DiskFull { .. } => "disk-full",
+            ConversionError::Io { .. } => "io",
+            ConversionError::VerificationFailed { .. } => "verify",
+            ConversionError::TimedOut { .. } => "timeout",
+            ConversionError::InvalidPattern { .. } => "pattern",
+        }
+    }
+
+    /// Classifies an I/O error that happened while reading `path`.
+    fn reading(path: &Path, error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => ConversionError::PermissionDenied {
+                path: path.to_path_buf(),
+            },
+            _ => ConversionError::UnreadableInput {
+                path: path.to_path_buf(),
+                reason: error.to_string(),
+            },
+        }
+    }
+
+    /// Classifies an I/O error that happened while writing `path`.
+    pub(crate) fn writing(path: &Path, error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                ConversionError::PermissionDenied {
+                    path: path.to_path_buf(),
+                }
+            }
+            io::ErrorKind::StorageFull => ConversionError::DiskFull {
+                path: path.to_path_buf(),
+            },
+            _ => ConversionError::Io {
+                path: path.to_path_buf(),
+                reason: error.to_string(),
+            },
+        }
+    }
+
+    /// Attaches `path` to an error reported by an image backend.
+    fn backend(path: &Path, error: BackendError) -> Self {
+        let path = path.to_path_buf();
+        match error {
+            BackendError::Unsupported(reason) => {
+                ConversionError::UnsupportedFormat { path, reason }
+            }
+            BackendError::Decode(reason) => ConversionError::DecodeFailed { path, reason },
+            BackendError::Encode(reason) => ConversionError::EncodeFailed { path, reason },
+        }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnreadableInput { path, reason } => {
+                write!(f, "Unable to read {:?}: {}", path, reason)
+            }
+            ConversionError::UnsupportedFormat { path, reason } => {
+                write!(f, "Unsupported format of {:?}: {}", path, reason)
+            }
+            ConversionError::DecodeFailed { path, reason } => {
+                write!(f, "Unable to decode {:?}: {}", path, reason)
+            }
+            ConversionError::EncodeFailed { path, reason } => {
+                write!(f, "Unable to encode {:?}: {}", path, reason)
+            }
+            ConversionError::PermissionDenied { path } => {
+                write!(f, "Permission denied for {:?}", path)
+            }
+            ConversionError::DiskFull { path } => {
+                write!(f, "No space left to write {:?}", path)
+            }
+            ConversionError::Io { path, reason } => {
+                write!(f, "Unable to write {:?}: {}", path, reason)
+            }
+            ConversionError::VerificationFailed { path, reason } => {
+                write!(f, "Verification of {:?} failed: {}", path, reason)
+            }
+            ConversionError::TimedOut { path, timeout } => {
+                write!(
+                    f,
+                    "Converting {:?} took longer than {} seconds",
+                    path,
+                    timeout.as_secs()
+                )
+            }
+            ConversionError::InvalidPattern {
+                path,
+                pattern,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "Invalid pattern {:?} for {:?}: {}",
+                    pattern, path, reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Snapshot of the conversion progress, sent after every processed file.
+#[derive(Debug, Clone)]
+pub struct ProgressInfo {
+    /// The file that was just processed.
+    pub current_file: PathBuf,
+    /// Number of files processed so far.
+    pub processed: usize,
+    /// Number of files found so far. Still grows while the scan is running.
+    pub total: usize,
+    pub elapsed: Duration,
+    pub files_per_second: f64,
+    /// Size of all processed input files.
+    pub bytes_read: u64,
+    /// Size of all written output files.
+    pub bytes_written: u64,
+    /// Estimated time until all files are converted, known once the scan finished.
+    pub eta: Option<Duration>,
+}
+
+/// Details about a successfully converted file.
+#[derive(Debug, Clone)]
+pub struct ConvertedFile {
+    /// JPEG quality of the written file.
+    pub quality: u8,
+    /// Name of the decoder that managed to read the input.
+    pub decoder: &'static str,
+    /// Size of the input file in bytes.
+    pub input_size: u64,
+    /// Size of the written JPEG in bytes.
+    pub output_size: u64,
+}
+
+/// Progress of a running conversion, in the order it happens.
+#[derive(Debug)]
+pub enum Event {
+    /// The folder scan has started, files are converted as soon as they are found.
+    ConversionStarted,
+    /// Number of files found so far while the scan is still running.
+    FilesDiscovered(usize),
+    /// The scan is done and found the given number of files in total.
+    DiscoveryFinished(usize),
+    /// The symlink (first path) points to one of its parent folders (second path) and was skipped.
+    SymlinkLoop(PathBuf, PathBuf),
+    /// Conversion of the given file has started.
+    FileStarted(PathBuf),
+    ProgressUpdate(ProgressInfo),
+    /// A file was written successfully.
+    FileConverted(PathBuf, ConvertedFile),
+    /// A file could not be converted, the remaining files are still processed.
+    FileFailed(ConversionError),
+    /// The first file was skipped because the second one was already converted to the same name.
+    DuplicateSkipped(PathBuf, PathBuf),
+    /// What happened to every file, sent before the conversion completes or fails.
+    ReportReady(ConversionReport),
+    ConversionComplete,
+    ConversionFailed(ConversionError),
+}
+
+/// Receives the events of a running conversion. Called from several threads.
+pub type EventSink = Arc<dyn Fn(Event) + Send + Sync>;
+
+impl Event {
+    /// The event as a single line JSON object, e.g. for wrapper scripts.
+    ///
+    /// Every object has an `event` field naming the event, the remaining fields depend on it.
+    pub fn to_json(&self) -> String {
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let event = match self {
+            Event::ConversionStarted => JsonEvent::JobStarted,
+            Event::FilesDiscovered(count) => JsonEvent::FilesDiscovered { count: *count },
+            Event::DiscoveryFinished(count) => JsonEvent::DiscoveryFinished { count: *count },
+            Event::SymlinkLoop(link, ancestor) => JsonEvent::FileSkipped {
+                input: path(link),
+                reason: "symlink_loop",
+                target: path(ancestor),
+            },
+            Event::FileStarted(input) => JsonEvent::FileStarted { input: path(input) },
+            Event::ProgressUpdate(progress) => JsonEvent::Progress {
+                processed: progress.processed,
+                total: progress.total,
+                bytes_read: progress.bytes_read,
+                bytes_written: progress.bytes_written,
+                eta_ms: progress.eta.map(|eta| eta.as_millis()),
+            },
+            Event::FileConverted(input, converted) => JsonEvent::FileDone {
+                input: path(input),
+                input_bytes: converted.input_size,
+                output_bytes: converted.output_size,
+                quality: converted.quality,
+                decoder: converted.decoder,
+            },
+            Event::FileFailed(e) => JsonEvent::FileFailed {
+                input: path(e.path()),
+                error: e.kind(),
+                message: e.to_string(),
+            },
+            Event::DuplicateSkipped(input, original) => JsonEvent::FileSkipped {
+                input: path(input),
+                reason: "duplicate",
+                target: path(original),
+            },
+            Event::ReportReady(report) => JsonEvent::Summary {
+                converted: report.count(FileStatus::Converted),
+                skipped: report.count(FileStatus::Skipped),
+                failed: report.count(FileStatus::Failed),
+                duplicate: report.count(FileStatus::Duplicate),
+                duration_ms: report.duration.as_millis(),
+            },
+            Event::ConversionComplete => JsonEvent::JobFinished {
+                aborted: false,
+                error: None,
+                message: None,
+            },
+            Event::ConversionFailed(e) => JsonEvent::JobFinished {
+                aborted: true,
+                error: Some(e.kind()),
+                message: Some(e.to_string()),
+            },
+        };
+        serde_json::to_string(&event).expect("an event can always be serialized")
+    }
+}
+
+/// Layout of [`Event::to_json`], named after the `event` field.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JsonEvent {
+    JobStarted,
+    FilesDiscovered {
+        count: usize,
+    },
+    DiscoveryFinished {
+        count: usize,
+    },
+    FileStarted {
+        input: String,
+    },
+    Progress {
+        processed: usize,
+        total: usize,
+        bytes_read: u64,
+        bytes_written: u64,
+        eta_ms: Option<u128>,
+    },
+    FileDone {
+        input: String,
+        input_bytes: u64,
+        output_bytes: u64,
+        quality: u8,
+        decoder: &'static str,
+    },
+    FileFailed {
+        input: String,
+        error: &'static str,
+        message: String,
+    },
+    FileSkipped {
+        input: String,
+        reason: &'static str,
+        target: String,
+    },
+    Summary {
+        converted: usize,
+        skipped: usize,
+        failed: usize,
+        duplicate: usize,
+        duration_ms: u128,
+    },
+    JobFinished {
+        aborted: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+/// The backends used for one conversion run.
+pub(crate) struct Decoders {
+    primary: Box<dyn ImageBackend>,
+    fallback: Option<Box<dyn ImageBackend>>,
+}
+
+impl Decoders {
+    pub(crate) fn new(options: &ConversionOptions) -> Self {
+        let primary = options.backend.create();
+        let fallback = options
+            .fallback
+            .and_then(|fallback| fallback.create(options.backend));
+        info!(
+            "Decoding with {}, falling back to {:?}",
+            primary.name(),
+            fallback.as_ref().map(|fallback| fallback.name())
+        );
+
+        Self { primary, fallback }
+    }
+
+    /// Decodes with the primary backend and retries with the fallback if that fails.
+    ///
+    /// Returns the image and the name of the decoder that read it.
+    fn decode(
+        &self,
+        input_file: &Path,
+        data: &[u8],
+    ) -> Result<(Box<dyn DecodedImage>, &'static str), ConversionError> {
+        let error = match self.primary.decode(data) {
+            Ok(image) => return Ok((image, self.primary.name())),
+            Err(e) => e,
+        };
+
+        if let Some(fallback) = &self.fallback {
+            warn!(
+                "{} failed to decode {:?} ({}), retrying with {}",
+                self.primary.name(),
+                input_file,
+                error,
+                fallback.name()
+            );
+            match fallback.decode(data) {
+                Ok(image) => return Ok((image, fallback.name())),
+                Err(e) => warn!("{} failed as well: {}", fallback.name(), e),
+            }
+        }
+        Err(ConversionError::backend(input_file, error))
+    }
+}
+
+/// Folders and single files to convert and how.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub options: ConversionOptions,
+}
+
+impl Job {
+    /// A job converting `inputs` into `output` with the default options.
+    pub fn new(inputs: Vec<PathBuf>, output: PathBuf) -> Self {
+        Self {
+            inputs,
+            output,
+            options: ConversionOptions::default(),
+        }
+    }
+
+    /// Runs the job on the current thread, see [`convert`].
+    pub fn run(&self, events: &EventSink) -> ConversionReport {
+        self.run_until(events, &AtomicBool::new(false))
+    }
+
+    /// Like [`Job::run`], but stops before the next file once `cancel` is set.
+    ///
+    /// A cancelled job still completes normally, its report lists the files handled so far.
+    pub fn run_until(&self, events: &EventSink, cancel: &AtomicBool) -> ConversionReport {
+        convert_until(
+            self.inputs.clone(),
+            self.output.clone(),
+            &self.options,
+            events,
+            cancel,
+        )
+    }
+
+    /// Runs the job on a new thread and returns its events as a channel.
+    ///
+    /// The channel closes once the conversion is done, the thread returns the report.
+    pub fn spawn(self) -> (JoinHandle<ConversionReport>, mpsc::Receiver<Event>) {
+        let (sender, events) = mpsc::channel();
+        let sink: EventSink = Arc::new(move |event| {
+            let _ = sender.send(event);
+        });
+        let handle = thread::spawn(move || self.run(&sink));
+        (handle, events)
+    }
+}
+
+/// Converts every heic file below the `inputs` into `output_path`.
+///
+/// Inputs can be folders or single files. Progress is reported to `events`, ending with the report and either `ConversionComplete`
+/// or `ConversionFailed`. The report is returned as well.
+pub fn convert(
+    inputs: Vec<PathBuf>,
+    output_path: PathBuf,
+    options: &ConversionOptions,
+    events: &EventSink,
+) -> ConversionReport {
+    convert_until(
+        inputs,
+        output_path,
+        options,
+        events,
+        &AtomicBool::new(false),
+    )
+}
+
+fn convert_until(
+    inputs: Vec<PathBuf>,
+    output_path: PathBuf,
+    options: &ConversionOptions,
+    events: &EventSink,
+    cancel: &AtomicBool,
+) -> ConversionReport {
+    // Walk directory, find all heic files, convert them to jpg and update progress
+    info!("Converting {:?}", inputs);
+    let mut report = ConversionReport::new(inputs.clone(), output_path.clone());
+    let decoders = Decoders::new(options);
+    let result = convert_inputs(
+        &decoders,
+        inputs,
+        output_path,
+        options,
+        &mut report,
+        events,
+        cancel,
+    );
+    report.duration = report.started.elapsed().unwrap_or_default();
+
+    // Send the result of the conversion back
+    events(Event::ReportReady(report.clone()));
+    match result {
+        Ok(_) => events(Event::ConversionComplete),
+        Err(e) => events(Event::ConversionFailed(e)),
+    }
+    report
+}
+
+fn convert_inputs(
+    decoders: &Decoders,
+    inputs: Vec<PathBuf>,
+    output_path: PathBuf,
+    options: &ConversionOptions,
+    report: &mut ConversionReport,
+    events: &EventSink,
+    cancel: &AtomicBool,
+) -> Result<(), ConversionError> {
+    // Start the conversion
+    info!("Converting {:?} to {:?}", inputs, output_path);
+
+    let mut walks = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let walk = Discovery::new(input.clone(), options.discovery.clone()).map_err(|e| {
+            ConversionError::InvalidPattern {
+                path: input,
+                pattern: e.pattern,
+                reason: e.reason,
+            }
+        })?;
+        walks.push(walk);
+    }
+    events(Event::ConversionStarted);
+
+    // Scan the inputs in the background and convert files as they come in
+    let found = AtomicUsize::new(0);
+    let (file_sender, heic_files) = mpsc::channel();
+    thread::scope(|scope| {
+        let discovery = {
+            let found = &found;
+            scope.spawn(move || discover_heic_files(walks, file_sender, found, events, cancel))
+        };
+
+        // Convert each heic file to jpg
+        let started = Instant::now();
+        let (mut bytes_read, mut bytes_written) = (0, 0);
+        let mut processed = 0;
+        // Output files written so far and the input they were converted from
+        let mut outputs = HashMap::new();
+        for discovered in heic_files.iter() {
+            if cancel.load(Ordering::Relaxed) {
+                info!("Conversion cancelled");
+                // Dropping the receiver stops the discovery as well
+                drop(heic_files);
+                let _ = discovery.join();
+                return Ok(());
+            }
+            let heic_file = match discovered {
+                Discovered::File(path) => path,
+                Discovered::SymlinkLoop { link, .. } => {
+                    report
+                        .entries
+                        .push(ReportEntry::new(link, FileStatus::Skipped));
+                    continue;
+                }
+            };
+
+            info!("Converting file {:?}", heic_file);
+            events(Event::FileStarted(heic_file.clone()));
+            let output_file = output_path.join(jpg_file_name(&heic_file));
+            let file_started = Instant::now();
+            let mut entry = ReportEntry::new(heic_file.clone(), FileStatus::Converted);
+            entry.output = Some(output_file.clone());
+            entry.input_size = fs::metadata(&heic_file).ok().map(|m| m.len());
+
+            // Convert the file
+            bytes_read += entry.input_size.unwrap_or_default();
+            let result = if let Some(original) = outputs.get(&output_file) {
+                // Several inputs with the same name in different folders, keep the first one
+                warn!(
+                    "{:?} would overwrite the output of {:?}",
+                    heic_file, original
+                );
+                entry.status = FileStatus::Duplicate;
+                entry.output = None;
+                entry.error = Some(format!("same output file as {:?}", original));
+                events(Event::DuplicateSkipped(
+                    heic_file.clone(),
+                    PathBuf::clone(original),
+                ));
+                None
+            } else {
+                Some(convert_file(
+                    decoders,
+                    heic_file.to_path_buf(),
+                    output_file.clone(),
+                    options,
+                ))
+            };
+            entry.duration = file_started.elapsed();
+            let failure = match result {
+                Some(Ok(converted)) => {
+                    entry.output_size = Some(converted.output_size);
+                    entry.quality = Some(converted.quality);
+                    entry.decoder = Some(converted.decoder);
+                    bytes_written += entry.output_size.unwrap_or_default();
+                    outputs.insert(output_file, heic_file.clone());
+                    events(Event::FileConverted(heic_file.to_path_buf(), converted));
+                    None
+                }
+                Some(Err(e)) => {
+                    entry.status = FileStatus::Failed;
+                    entry.output = None;
+                    entry.error = Some(e.to_string());
+                    Some(e)
+                }
+                None => None,
+            };
+            report.entries.push(entry);
+            if let Some(e) = failure {
+                // Every following file would fail the same way
+                if let ConversionError::DiskFull { .. } = e {
+                    // Still list the remaining files, the scan runs to its end
+                    skip_remaining(heic_files.iter(), report);
+                    let _ = discovery.join();
+                    return Err(e);
+                }
+                warn!("{}", e);
+                events(Event::FileFailed(e));
+            }
+
+            // Update the progress, the total might still grow while scanning
+            processed += 1;
+            let total = found.load(Ordering::Relaxed).max(processed);
+            let elapsed = started.elapsed();
+            let files_per_second = processed as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+            let eta = discovery
+                .is_finished()
+                .then(|| Duration::from_secs_f64((total - processed) as f64 / files_per_second));
+            events(Event::ProgressUpdate(ProgressInfo {
+                current_file: heic_file,
+                processed,
+                total,
+                elapsed,
+                files_per_second,
+                bytes_read,
+                bytes_written,
+                eta,
+            }));
+        }
+        let _ = discovery.join();
+        if cancel.load(Ordering::Relaxed) {
+            info!("Conversion cancelled");
+        } else {
+            info!("Conversion complete");
+        }
+        Ok(())
+    })
+}
+
+/// Lists every file still coming from the scan as skipped because the disk is full.
+fn skip_remaining(files: impl Iterator<Item = Discovered>, report: &mut ConversionReport) {
+    for discovered in files {
+        let path = match discovered {
+            Discovered::File(path) => path,
+            Discovered::SymlinkLoop { link, .. } => link,
+        };
+        let mut entry = ReportEntry::new(path, FileStatus::Skipped);
+        entry.error = Some("not converted because the disk is full".to_string());
+        report.entries.push(entry);
+    }
+}
+
+/// Converts a single file, in a helper process if `options.isolate` or `options.timeout` is set.
+pub(crate) fn convert_file(
+    decoders: &Decoders,
+    input_file: PathBuf,
+    output_file: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConvertedFile, ConversionError> {
+    if options.isolate || options.timeout.is_some() {
+        return decode_helper::convert(input_file, output_file, options);
+    }
+    write_atomically(&output_file.clone(), options.sync, |temp_file| {
+        convert_heic_to_jpg(decoders, input_file, output_file, temp_file, options)
+    })
+}
+
+/// Runs the directory walks one after another and sends every heic file to `files` as soon as it is found.
+///
+/// Stops early once `cancel` is set or the receiving side is gone, e.g. because the conversion
+/// failed.
+fn discover_heic_files(
+    walks: Vec<Discovery>,
+    files: mpsc::Sender<Discovered>,
+    found: &AtomicUsize,
+    events: &EventSink,
+    cancel: &AtomicBool,
+) {
+    let mut last_update = Instant::now();
+    for discovered in walks.into_iter().flat_map(|walk| walk.walk(cancel)) {
+        if let Discovered::SymlinkLoop { link, ancestor } = &discovered {
+            events(Event::SymlinkLoop(link.clone(), ancestor.clone()));
+            // Still passed on, so it ends up in the report
+            if files.send(discovered).is_err() {
+                return;
+            }
+            continue;
+        }
+
+        let count = found.fetch_add(1, Ordering::Relaxed) + 1;
+        if files.send(discovered).is_err() {
+            return;
+        }
+        if last_update.elapsed() >= DISCOVERY_UPDATE_INTERVAL {
+            last_update = Instant::now();
+            events(Event::FilesDiscovered(count));
+        }
+    }
+
+    if cancel.load(Ordering::Relaxed) {
+        info!("Scan cancelled");
+        return;
+    }
+    let count = found.load(Ordering::Relaxed);
+    info!("Found {} heic files", count);
+    events(Event::DiscoveryFinished(count));
+}
+
+/// Runs `write` on a temporary file next to `output_file` and moves it into place once
+/// `write` succeeded.
+///
+/// Outputs are either complete or absent, a killed app leaves at most a hidden
+/// `.heic2jpg-*.jpg` file behind.
+pub fn write_atomically<T>(
+    output_file: &Path,
+    sync: bool,
+    write: impl FnOnce(&Path) -> Result<T, ConversionError>,
+) -> Result<T, ConversionError> {
+    let output_dir = output_file.parent().unwrap_or(Path::new("."));
+    let temp_file = tempfile::Builder::new()
+        .prefix(".heic2jpg-")
+        .suffix(".jpg")
+        // Same permissions `fs::write` would use instead of the private default
+        .permissions(fs::Permissions::from_mode(0o666))
+        .tempfile_in(output_dir)
+        .map_err(|e| ConversionError::writing(output_file, e))?;
+
+    let result = write(temp_file.path())?;
+
+    temp_file
+        .persist(output_file)
+        .map_err(|e| ConversionError::writing(output_file, e.error))?;
+    if sync {
+        // Make the rename itself survive a crash as well
+        File::open(output_dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| ConversionError::writing(output_file, e))?;
+    }
+    Ok(result)
+}
+
+/// Converts a single file into `write_to` and returns how it was done.
+///
+/// Errors about the written file refer to `output_file`.
+pub(crate) fn convert_heic_to_jpg(
+    decoders: &Decoders,
+    input_file: PathBuf,
+    output_file: PathBuf,
+    write_to: &Path,
+    options: &ConversionOptions,
+) -> Result<ConvertedFile, ConversionError> {
+    // Read the input file ourselves, ImageMagick only takes UTF-8 paths
+    info!("Reading file {:?}", input_file);
+    let data = std::fs::read(&input_file).map_err(|e| ConversionError::reading(&input_file, e))?;
+    let encoded = encode(decoders, &input_file, &data, options)?;
+
+    write_file(write_to, &encoded.blob, options.sync)
+        .map_err(|e| ConversionError::writing(&output_file, e))?;
+
+    if let Some(verification) = options.verify {
+        let verified = std::fs::read(write_to)
+            .map_err(|e| format!("unable to read the written file: {}", e))
+            .and_then(|written| {
+                verification::verify(
+                    verification,
+                    decoders.primary.as_ref(),
+                    encoded.image.as_ref(),
+                    &written,
+                )
+            });
+        if let Err(reason) = verified {
+            return Err(ConversionError::VerificationFailed {
+                path: output_file,
+                reason,
+            });
+        }
+    }
+
+    Ok(encoded.converted(data.len()))
+}
+
+/// Converts a HEIC image held in memory and returns the JPEG along with how it was done.
+///
+/// Uses the same quality, backend and verification options as folder jobs. Errors and logs
+/// refer to the image as `name`, e.g. `-` for stdin.
+pub fn convert_blob(
+    name: &Path,
+    data: &[u8],
+    options: &ConversionOptions,
+) -> Result<(Vec<u8>, ConvertedFile), ConversionError> {
+    let decoders = Decoders::new(options);
+    let encoded = encode(&decoders, name, data, options)?;
+
+    if let Some(verification) = options.verify {
+        verification::verify(
+            verification,
+            decoders.primary.as_ref(),
+            encoded.image.as_ref(),
+            &encoded.blob,
+        )
+        .map_err(|reason| ConversionError::VerificationFailed {
+            path: name.to_path_buf(),
+            reason,
+        })?;
+    }
+
+    let converted = encoded.converted(data.len());
+    Ok((encoded.blob, converted))
+}
+
+/// A JPEG encoded from a HEIC image, before it is written anywhere.
+struct Encoded {
+    blob: Vec<u8>,
+    /// The decoded (and possibly downscaled) image the JPEG was encoded from.
+    image: Box<dyn DecodedImage>,
+    quality: u8,
+    decoder: &'static str,
+}
+
+impl Encoded {
+    fn converted(&self, input_size: usize) -> ConvertedFile {
+        ConvertedFile {
+            quality: self.quality,
+            decoder: self.decoder,
+            input_size: input_size as u64,
+            output_size: self.blob.len() as u64,
+        }
+    }
+}
+
+/// Decodes `data` and encodes it as JPEG according to `options.quality`.
+fn encode(
+    decoders: &Decoders,
+    input_file: &Path,
+    data: &[u8],
+    options: &ConversionOptions,
+) -> Result<Encoded, ConversionError> {
+    check_heif_header(input_file, data)?;
+
+    let (mut image, decoder) = decoders.decode(input_file, data)?;
+
+    // Convert the image to jpg
+    info!("Converting to jpg");
+    let (blob, quality) = match options.quality {
+        QualityMode::Fixed(quality) => (image.encode_jpeg(quality), quality),
+        QualityMode::TargetSize {
+            max_bytes,
+            allow_downscale,
+        } => match encode_within_budget(image.as_mut(), max_bytes, allow_downscale) {
+            Ok((blob, quality)) => {
+                info!(
+                    "Picked quality {} ({} bytes) for {:?}",
+                    quality,
+                    blob.len(),
+                    input_file
+                );
+                (Ok(blob), quality)
+            }
+            Err(e) => (Err(e), 0),
+        },
+    };
+    let blob = blob.map_err(|e| ConversionError::backend(input_file, e))?;
+
+    Ok(Encoded {
+        blob,
+        image,
+        quality,
+        decoder,
+    })
+}
+
+fn write_file(path: &Path, data: &[u8], sync: bool) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Name of the JPEG written for `input_file`, e.g. `IMG_0001.jpg` for `IMG_0001.HEIC`.
+///
+/// Works on the raw `OsStr` so file names that are not valid UTF-8 are kept as they are.
+pub(crate) fn jpg_file_name(input_file: &Path) -> OsString {
+    let mut name = input_file
+        .file_stem()
+        .map(|stem| stem.to_os_string())
+        .unwrap_or_else(|| OsString::from("image"));
+    name.push(".jpg");
+    name
+}
+
+/// Makes sure the input starts like an ISO BMFF file compatible with a HEIF brand.
+fn check_heif_header(input_file: &Path, data: &[u8]) -> Result<(), ConversionError> {
+    let is_heif = ftyp_brands(data).is_some_and(|brands| {
+        brands
+            .iter()
+            .any(|brand| HEIF_BRANDS.iter().any(|known| known[..] == **brand))
+    });
+
+    if !is_heif {
+        return Err(ConversionError::UnsupportedFormat {
+            path: input_file.to_path_buf(),
+            reason: format!(
+                "unknown file type {:?}",
+                String::from_utf8_lossy(data.get(8..12).unwrap_or_default())
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The major brand followed by the compatible brands of the `ftyp` box `data` starts with.
+fn ftyp_brands(data: &[u8]) -> Option<Vec<&[u8]>> {
+    if data.get(4..8)? != b"ftyp" {
+        return None;
+    }
+    let major = data.get(8..12)?;
+    let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    // A size of 0 extends the box to the end of the file
+    let end = match size {
+        0 => data.len(),
+        size => size.min(data.len()),
+    };
+
+    let mut brands = vec![major];
+    // The minor version sits between the major and the compatible brands
+    if let Some(compatible) = data.get(16..end) {
+        brands.extend(compatible.chunks_exact(4));
+    }
+    Some(brands)
+}
+
+/// Searches for the highest quality at which the encoded image stays within `max_bytes`.
+///
+/// When downscaling is allowed, the image is shrunk rather than encoded below
+/// [`MIN_QUALITY_BEFORE_DOWNSCALE`]. If nothing fits, the smallest encoding is returned.
+fn encode_within_budget(
+    image: &mut dyn DecodedImage,
+    max_bytes: u64,
+    allow_downscale: bool,
+) -> Result<(Vec<u8>, u8), BackendError> {
+    let min_quality = if allow_downscale {
+        MIN_QUALITY_BEFORE_DOWNSCALE
+    } else {
+        1
+    };
+
+    loop {
+        if let Some(found) = search_quality(image, min_quality, max_bytes)? {
+            return Ok(found);
+        }
+
+        let (width, height) = image.dimensions();
+        if !allow_downscale || width.max(height) <= MIN_DOWNSCALE_EDGE {
+            break;
+        }
+        let width = ((width as f64 * DOWNSCALE_STEP) as usize).max(1);
+        let height = ((height as f64 * DOWNSCALE_STEP) as usize).max(1);
+        info!(
+            "Downscaling to {}x{} to fit {} bytes",
+            width, height, max_bytes
+        );
+        image.resize(width, height)?;
+    }
+
+    // Even the smallest size did not reach the quality floor, so give up on it. Without
+    // downscaling the search already went all the way down.
+    if min_quality > 1 {
+        if let Some(found) = search_quality(image, 1, max_bytes)? {
+            return Ok(found);
+        }
+    }
+    warn!(
+        "Unable to fit image into {} bytes, using lowest quality",
+        max_bytes
+    );
+    Ok((image.encode_jpeg(1)?, 1))
+}
+
+/// Binary search over `min_quality..=100` for the highest quality that fits.
+fn search_quality(
+    image: &dyn DecodedImage,
+    min_quality: u8,
+    max_bytes: u64,
+) -> Result<Option<(Vec<u8>, u8)>, BackendError> {
+    let (mut low, mut high) = (min_quality, 100u8);
+    let mut best = None;
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let blob = image.encode_jpeg(quality)?;
+        if blob.len() as u64 <= max_bytes {
+            best = Some((blob, quality));
+            low = quality + 1;
+        } else if quality == min_quality {
+            break;
+        } else {
+            high = quality - 1;
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An `ftyp` box with the given brands followed by some other data.
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + 4 * compatible.len() as u32;
+        let mut data = size.to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(major);
+        data.extend_from_slice(&[0; 4]);
+        for brand in compatible {
+            data.extend_from_slice(*brand);
+        }
+        data.extend_from_slice(b"\0\0\0\x08meta");
+        data
+    }
+
+    fn is_heif(data: &[u8]) -> bool {
+        check_heif_header(Path::new("test.heic"), data).is_ok()
+    }
+
+    #[test]
+    fn heif_header_accepts_known_major_brands() {
+        assert!(is_heif(&ftyp(b"heic", &[])));
+        assert!(is_heif(&ftyp(b"mif1", &[b"heic"])));
+        assert!(is_heif(&ftyp(b"mif2", &[])));
+    }
+
+    #[test]
+    fn heif_header_accepts_known_compatible_brands() {
+        assert!(is_heif(&ftyp(b"avif", &[b"avif", b"mif1", b"miaf"])));
+        assert!(is_heif(&ftyp(
+            b"MiHE",
+            &[b"MiHE", b"miaf", b"MiHB", b"heic"]
+        )));
+    }
+
+    #[test]
+    fn heif_header_rejects_other_files() {
+        assert!(!is_heif(&ftyp(b"isom", &[b"isom", b"mp41"])));
+        assert!(!is_heif(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"));
+        assert!(!is_heif(b""));
+        // Brands after the end of the box do not count
+        let mut data = ftyp(b"isom", &[]);
+        data.extend_from_slice(b"heic");
+        assert!(!is_heif(&data));
+    }
+
+    #[test]
+    fn heif_header_error_names_the_major_brand() {
+        let error = check_heif_header(Path::new("video.heic"), &ftyp(b"isom", &[])).unwrap_err();
+        assert!(matches!(
+            &error,
+            ConversionError::UnsupportedFormat { path, reason }
+                if path == Path::new("video.heic") && reason.contains("isom")
+        ));
+    }
+
+    #[test]
+    fn jpg_file_names_replace_the_extension() {
+        assert_eq!(jpg_file_name(Path::new("/a/IMG_0001.heic")), "IMG_0001.jpg");
+        assert_eq!(jpg_file_name(Path::new("IMG_0001.HEIF")), "IMG_0001.jpg");
+        assert_eq!(
+            jpg_file_name(Path::new("holiday.2024.heic")),
+            "holiday.2024.jpg"
+        );
+        assert_eq!(jpg_file_name(Path::new("/a/.heic")), ".heic.jpg");
+        assert_eq!(jpg_file_name(Path::new("/")), "image.jpg");
+    }
+
+    /// Encodes to 100 bytes per quality step, so the size is known in advance.
+    struct FakeImage;
+
+    impl DecodedImage for FakeImage {
+        fn dimensions(&self) -> (usize, usize) {
+            (1, 1)
+        }
+
+        fn resize(&mut self, _width: usize, _height: usize) -> Result<(), BackendError> {
+            Ok(())
+        }
+
+        fn encode_jpeg(&self, quality: u8) -> Result<Vec<u8>, BackendError> {
+            Ok(vec![0; usize::from(quality) * 100])
+        }
+
+        fn rgb_pixels(&self) -> Result<Vec<u8>, BackendError> {
+            Ok(vec![0; 3])
+        }
+    }
+
+    /// Counts how often it was encoded.
+    #[derive(Default)]
+    struct CountingImage {
+        encodes: std::cell::Cell<usize>,
+    }
+
+    impl DecodedImage for CountingImage {
+        fn dimensions(&self) -> (usize, usize) {
+            (4000, 3000)
+        }
+
+        fn resize(&mut self, _width: usize, _height: usize) -> Result<(), BackendError> {
+            Ok(())
+        }
+
+        fn encode_jpeg(&self, quality: u8) -> Result<Vec<u8>, BackendError> {
+            self.encodes.set(self.encodes.get() + 1);
+            FakeImage.encode_jpeg(quality)
+        }
+
+        fn rgb_pixels(&self) -> Result<Vec<u8>, BackendError> {
+            Ok(vec![0; 3])
+        }
+    }
+
+    #[test]
+    fn missed_budgets_are_searched_only_once_without_downscaling() {
+        let mut image = CountingImage::default();
+        let (blob, quality) = encode_within_budget(&mut image, 50, false).unwrap();
+        assert_eq!((blob.len(), quality), (100, 1));
+        // Six steps of the search down to quality 1 and the final encode
+        assert_eq!(image.encodes.get(), 7);
+    }
+
+    fn search(min_quality: u8, max_bytes: u64) -> Option<u8> {
+        search_quality(&FakeImage, min_quality, max_bytes)
+            .unwrap()
+            .map(|(blob, quality)| {
+                assert_eq!(blob.len(), usize::from(quality) * 100);
+                quality
+            })
+    }
+
+    #[test]
+    fn search_quality_finds_the_highest_fitting_quality() {
+        assert_eq!(search(50, 7_350), Some(73));
+        assert_eq!(search(50, 7_300), Some(73));
+        assert_eq!(search(50, 5_000), Some(50));
+        assert_eq!(search(1, 100), Some(1));
+    }
+
+    #[test]
+    fn search_quality_stops_at_the_bounds() {
+        assert_eq!(search(50, 1_000_000), Some(100));
+        assert_eq!(search(100, 10_000), Some(100));
+        assert_eq!(search(50, 4_999), None);
+        assert_eq!(search(1, 99), None);
+    }
+
+    /// Decodes every file with a HEIF header to a [`FakeImage`].
+    struct FakeBackend;
+
+    impl ImageBackend for FakeBackend {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn decode(&self, _data: &[u8]) -> Result<Box<dyn DecodedImage>, BackendError> {
+            Ok(Box::new(FakeImage))
+        }
+    }
+
+    /// Runs `convert_inputs` with the fake backend and returns its result and events.
+    fn convert_with_fake_backend(
+        inputs: Vec<PathBuf>,
+        output: &Path,
+        report: &mut ConversionReport,
+    ) -> (Result<(), ConversionError>, Vec<Event>) {
+        let decoders = Decoders {
+            primary: Box::new(FakeBackend),
+            fallback: None,
+        };
+        let options = ConversionOptions {
+            quality: QualityMode::Fixed(50),
+            ..ConversionOptions::default()
+        };
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink: EventSink = {
+            let events = events.clone();
+            Arc::new(move |event| events.lock().unwrap().push(event))
+        };
+        let result = convert_inputs(
+            &decoders,
+            inputs,
+            output.to_path_buf(),
+            &options,
+            report,
+            &sink,
+            &AtomicBool::new(false),
+        );
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        (result, events)
+    }
+
+    #[test]
+    fn files_are_converted_as_the_scan_finds_them() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        fs::create_dir(input.path().join("2024")).unwrap();
+        fs::write(input.path().join("a.heic"), ftyp(b"heic", &[])).unwrap();
+        fs::write(input.path().join("2024/b.HEIC"), ftyp(b"mif1", &[])).unwrap();
+        fs::write(input.path().join("broken.heic"), b"not an image").unwrap();
+        fs::write(input.path().join("notes.txt"), b"").unwrap();
+
+        let mut report = ConversionReport::new(vec![input.path().into()], output.path().into());
+        let (result, events) =
+            convert_with_fake_backend(vec![input.path().into()], output.path(), &mut report);
+
+        assert!(result.is_ok());
+        assert_eq!(report.count(FileStatus::Converted), 2);
+        assert_eq!(report.count(FileStatus::Failed), 1);
+        let mut written: Vec<_> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        written.sort();
+        assert_eq!(written, ["a.jpg", "b.jpg"]);
+        assert_eq!(fs::read(output.path().join("a.jpg")).unwrap().len(), 5000);
+
+        assert!(matches!(events.first(), Some(Event::ConversionStarted)));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::DiscoveryFinished(3))));
+        let started = events
+            .iter()
+            .filter(|event| matches!(event, Event::FileStarted(_)))
+            .count();
+        assert_eq!(started, 3);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::FileFailed(ConversionError::UnsupportedFormat { path, .. })
+                if path.ends_with("broken.heic")
+        )));
+        let progress: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::ProgressUpdate(progress) => Some((progress.processed, progress.total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(progress.len(), 3);
+        assert!(progress
+            .iter()
+            .all(|(processed, total)| processed <= total && *total <= 3));
+        assert_eq!(progress.last(), Some(&(3, 3)));
+    }
+
+    #[test]
+    fn same_named_files_are_converted_once() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        for folder in ["a", "b"] {
+            fs::create_dir(input.path().join(folder)).unwrap();
+            fs::write(
+                input.path().join(folder).join("IMG.heic"),
+                ftyp(b"heic", &[]),
+            )
+            .unwrap();
+        }
+
+        let mut report = ConversionReport::new(vec![input.path().into()], output.path().into());
+        let (result, events) =
+            convert_with_fake_backend(vec![input.path().into()], output.path(), &mut report);
+
+        assert!(result.is_ok());
+        assert_eq!(report.count(FileStatus::Converted), 1);
+        assert_eq!(report.count(FileStatus::Duplicate), 1);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::DuplicateSkipped(..))));
+    }
+
+    /// Files in `dir` left behind by [`write_atomically`].
+    fn leftover_temp_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains(".heic2jpg-"))
+            .collect()
+    }
+
+    #[test]
+    fn atomic_writes_replace_the_output_file() {
+        let output = tempfile::tempdir().unwrap();
+        let output_file = output.path().join("IMG.jpg");
+        fs::write(&output_file, b"old").unwrap();
+
+        let written = write_atomically(&output_file, true, |temp_file| {
+            assert_ne!(temp_file, output_file);
+            fs::write(temp_file, b"new").unwrap();
+            Ok(3)
+        });
+
+        assert_eq!(written.unwrap(), 3);
+        assert_eq!(fs::read(&output_file).unwrap(), b"new");
+        assert!(leftover_temp_files(output.path()).is_empty());
+    }
+
+    #[test]
+    fn failed_atomic_writes_leave_nothing_behind() {
+        let output = tempfile::tempdir().unwrap();
+        let output_file = output.path().join("IMG.jpg");
+
+        let written: Result<(), _> = write_atomically(&output_file, false, |temp_file| {
+            fs::write(temp_file, b"partial").unwrap();
+            Err(ConversionError::writing(
+                &output_file,
+                io::Error::from(io::ErrorKind::StorageFull),
+            ))
+        });
+
+        assert!(matches!(written, Err(ConversionError::DiskFull { .. })));
+        assert!(!output_file.exists());
+        assert!(leftover_temp_files(output.path()).is_empty());
+    }
+
+    #[test]
+    fn atomic_writes_need_an_existing_folder() {
+        let output = tempfile::tempdir().unwrap();
+        let output_file = output.path().join("missing").join("IMG.jpg");
+
+        let written = write_atomically(&output_file, false, |_| Ok(()));
+
+        assert!(matches!(written, Err(ConversionError::Io { path, .. }) if path == output_file));
+    }
+
+    #[test]
+    fn files_after_a_full_disk_are_listed_as_skipped() {
+        let (files, heic_files) = mpsc::channel();
+        files.send(Discovered::File("/in/a.heic".into())).unwrap();
+        files
+            .send(Discovered::SymlinkLoop {
+                link: "/in/loop".into(),
+                ancestor: "/in".into(),
+            })
+            .unwrap();
+        drop(files);
+
+        let mut report = ConversionReport::new(vec!["/in".into()], "/out".into());
+        skip_remaining(heic_files.iter(), &mut report);
+
+        let skipped: Vec<_> = report.entries.iter().map(|entry| &entry.input).collect();
+        assert_eq!(skipped, [Path::new("/in/a.heic"), Path::new("/in/loop")]);
+        assert!(report.entries.iter().all(|entry| {
+            entry.status == FileStatus::Skipped && entry.error.as_deref().unwrap().contains("disk")
+        }));
+    }
+
+    fn event_json(event: Event) -> serde_json::Value {
+        let json = event.to_json();
+        assert!(!json.contains('\n'));
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn events_are_single_line_json_objects() {
+        use serde_json::json;
+
+        assert_eq!(
+            event_json(Event::ConversionStarted),
+            json!({"event": "job_started"})
+        );
+        assert_eq!(
+            event_json(Event::FilesDiscovered(3)),
+            json!({"event": "files_discovered", "count": 3})
+        );
+        assert_eq!(
+            event_json(Event::DiscoveryFinished(4)),
+            json!({"event": "discovery_finished", "count": 4})
+        );
+        assert_eq!(
+            event_json(Event::SymlinkLoop("/in/loop".into(), "/in".into())),
+            json!({"event": "file_skipped", "input": "/in/loop", "reason": "symlink_loop", "target": "/in"})
+        );
+        assert_eq!(
+            event_json(Event::FileStarted("/in/\"Föto\"\n📷.heic".into())),
+            json!({"event": "file_started", "input": "/in/\"Föto\"\n📷.heic"})
+        );
+        let progress = ProgressInfo {
+            current_file: "/in/a.heic".into(),
+            processed: 1,
+            total: 2,
+            elapsed: Duration::from_secs(1),
+            files_per_second: 1.0,
+            bytes_read: 2000,
+            bytes_written: 1000,
+            eta: None,
+        };
+        assert_eq!(
+            event_json(Event::ProgressUpdate(progress.clone())),
+            json!({"event": "progress", "processed": 1, "total": 2, "bytes_read": 2000, "bytes_written": 1000, "eta_ms": null})
+        );
+        assert_eq!(
+            event_json(Event::ProgressUpdate(ProgressInfo {
+                eta: Some(Duration::from_millis(1500)),
+                ..progress
+            }))["eta_ms"],
+            1500
+        );
+        let converted = ConvertedFile {
+            quality: 92,
+            decoder: "libheif",
+            input_size: 2000,
+            output_size: 1000,
+        };
+        assert_eq!(
+            event_json(Event::FileConverted("/in/a.heic".into(), converted)),
+            json!({"event": "file_done", "input": "/in/a.heic", "input_bytes": 2000, "output_bytes": 1000, "quality": 92, "decoder": "libheif"})
+        );
+        let error = ConversionError::DiskFull {
+            path: "/out/a.jpg".into(),
+        };
+        assert_eq!(
+            event_json(Event::FileFailed(error.clone())),
+            json!({"event": "file_failed", "input": "/out/a.jpg", "error": "disk-full", "message": error.to_string()})
+        );
+        assert_eq!(
+            event_json(Event::DuplicateSkipped(
+                "/in/b/a.heic".into(),
+                "/in/a.heic".into()
+            )),
+            json!({"event": "file_skipped", "input": "/in/b/a.heic", "reason": "duplicate", "target": "/in/a.heic"})
+        );
+        let mut report = ConversionReport::new(vec!["/in".into()], "/out".into());
+        report.duration = Duration::from_millis(20);
+        report.entries = vec![
+            ReportEntry::new("/in/a.heic".into(), FileStatus::Converted),
+            ReportEntry::new("/in/b.heic".into(), FileStatus::Failed),
+        ];
+        assert_eq!(
+            event_json(Event::ReportReady(report)),
+            json!({"event": "summary", "converted": 1, "skipped": 0, "failed": 1, "duplicate": 0, "duration_ms": 20})
+        );
+        assert_eq!(
+            event_json(Event::ConversionComplete),
+            json!({"event": "job_finished", "aborted": false})
+        );
+        assert_eq!(
+            event_json(Event::ConversionFailed(error.clone())),
+            json!({"event": "job_finished", "aborted": true, "error": "disk-full", "message": error.to_string()})
+        );
+    }
+
+    #[test]
+    fn cancelling_stops_the_scan() {
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("IMG.heic"), ftyp(b"heic", &[])).unwrap();
+        let walk = Discovery::new(input.path().into(), DiscoveryOptions::default()).unwrap();
+        let (files, found_files) = mpsc::channel();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink: EventSink = {
+            let events = events.clone();
+            Arc::new(move |event| events.lock().unwrap().push(event))
+        };
+
+        let found = AtomicUsize::new(0);
+        discover_heic_files(vec![walk], files, &found, &sink, &AtomicBool::new(true));
+
+        assert_eq!(found_files.iter().count(), 0);
+        assert_eq!(found.load(Ordering::Relaxed), 0);
+        // A cancelled scan does not claim to be complete
+        assert!(events.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,596 @@
+//! Runs the conversion of a single file in a separate process.
+//!
+//! The helper is the same binary started with [`HELPER_ARG`]. It converts one file and reports
+//! the outcome as a single line on stdout, so a crash in a decoder only fails that file. Unless
+//! disabled, the helper runs inside the [`sandbox`](crate::sandbox) and only writes to the
+//! temporary file it is handed, which is moved into place once the helper succeeded.
+//!
+//! Programs using [`ConversionOptions::isolate`] or [`ConversionOptions::timeout`] have to call
+//! [`run`] when started with [`HELPER_ARG`]. The helper takes everything it needs from its
+//! arguments, including [`ConversionOptions::resource_limits`].
+
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+use crate::backend::{self, BackendKind, FallbackDecoder};
+use crate::conversion::{
+    self, ConversionError, ConversionOptions, ConvertedFile, Decoders, QualityMode,
+};
+use crate::sandbox;
+
+/// First argument that turns the binary into a decode helper.
+pub const HELPER_ARG: &str = "--decode-helper";
+/// How often a running helper is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Converts `input_file` to `output_file` in a helper process.
+///
+/// A helper exceeding the timeout is killed, which also frees everything its decoder held on to.
+pub(crate) fn convert(
+    input_file: PathBuf,
+    output_file: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConvertedFile, ConversionError> {
+    // The temporary file is created up front, the sandboxed helper cannot create files itself
+    conversion::write_atomically(&output_file.clone(), options.sync, |temp_file| {
+        let spawn_failed = |e: std::io::Error| ConversionError::Io {
+            path: input_file.clone(),
+            reason: format!("unable to start the decoder process: {}", e),
+        };
+        let program = std::env::current_exe().map_err(spawn_failed)?;
+        // Removed once the helper is gone, even if it had to be killed
+        let helper_temp_dir = tempfile::Builder::new()
+            .prefix(".heic2jpg-helper-")
+            .tempdir()
+            .map_err(spawn_failed)?;
+        let mut child = Command::new(program)
+            .arg(HELPER_ARG)
+            .args(helper_args(
+                &input_file,
+                temp_file,
+                helper_temp_dir.path(),
+                options,
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(spawn_failed)?;
+
+        let Some(status) = wait(&mut child, options.timeout) else {
+            return Err(ConversionError::TimedOut {
+                path: input_file,
+                timeout: options.timeout.unwrap_or_default(),
+            });
+        };
+
+        let mut response = String::new();
+        if let Some(mut stdout) = child.stdout.take() {
+            let _ = stdout.read_to_string(&mut response);
+        }
+        match response.lines().next() {
+            Some(line) if status.success() => parse_response(line, input_file, output_file),
+            _ => Err(ConversionError::DecodeFailed {
+                path: input_file,
+                reason: describe_exit(status),
+            }),
+        }
+    })
+}
+
+/// Waits for the helper to exit, killing it once `timeout` has passed.
+///
+/// Returns `None` if the helper was killed.
+fn wait(child: &mut Child, timeout: Option<Duration>) -> Option<ExitStatus> {
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Unable to check on the decoder process: {}", e);
+                return child.wait().ok();
+            }
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() > timeout) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn describe_exit(status: ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+
+    match status.signal() {
+        Some(signal) => format!("the decoder process crashed (signal {})", signal),
+        None => format!("the decoder process failed ({})", status),
+    }
+}
+
+fn helper_args(
+    input_file: &Path,
+    output_file: &Path,
+    temp_dir: &Path,
+    options: &ConversionOptions,
+) -> Vec<OsString> {
+    let mut args = vec![
+        OsString::from("--temp-dir"),
+        temp_dir.into(),
+        OsString::from("--backend"),
+        options.backend.id().into(),
+    ];
+    if !options.sandbox {
+        args.push("--no-sandbox".into());
+    }
+    if options.sync {
+        args.push("--sync".into());
+    }
+    // Always passed, the helper would use the default fallback otherwise
+    args.push("--fallback".into());
+    args.push(options.fallback.map_or("none", FallbackDecoder::id).into());
+    match options.quality {
+        QualityMode::Fixed(quality) => {
+            args.push("--quality".into());
+            args.push(quality.to_string().into());
+        }
+        QualityMode::TargetSize {
+            max_bytes,
+            allow_downscale,
+        } => {
+            args.push("--target-size".into());
+            args.push(max_bytes.to_string().into());
+            if allow_downscale {
+                args.push("--downscale".into());
+            }
+        }
+    }
+    if let Some(verification) = options.verify {
+        args.push(format!("--verify={}", verification).into());
+    }
+    let limits = options.resource_limits;
+    let limits = [
+        ("memory-limit", limits.memory),
+        ("disk-limit", limits.disk),
+        ("threads", limits.threads),
+    ];
+    for (name, limit) in limits {
+        if let Some(limit) = limit {
+            args.push(format!("--{}={}", name, limit).into());
+        }
+    }
+    args.push("--".into());
+    args.push(input_file.into());
+    args.push(output_file.into());
+    args
+}
+
+/// A single conversion as passed to the helper on its command line.
+struct Request {
+    input_file: PathBuf,
+    output_file: PathBuf,
+    /// Private folder for temporary files of the decoders.
+    temp_dir: Option<PathBuf>,
+    options: ConversionOptions,
+}
+
+impl Request {
+    fn parse(args: impl Iterator<Item = OsString>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        let mut options = ConversionOptions::default();
+        let mut downscale = false;
+        let mut target_size = None;
+        let mut temp_dir = None;
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                break;
+            }
+            if arg == "--temp-dir" {
+                // Taken as it is, the path need not be UTF-8
+                temp_dir = Some(PathBuf::from(
+                    args.next().ok_or("missing value for \"--temp-dir\"")?,
+                ));
+                continue;
+            }
+            if arg == "--downscale" {
+                downscale = true;
+                continue;
+            }
+            if arg == "--no-sandbox" {
+                options.sandbox = false;
+                continue;
+            }
+            if arg == "--sync" {
+                options.sync = true;
+                continue;
+            }
+            if let Some(verify) = arg.to_str().and_then(|arg| arg.strip_prefix("--verify=")) {
+                options.verify = Some(verify.parse()?);
+                continue;
+            }
+            if let Some((name, limit)) = arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix("--"))
+                .and_then(|arg| arg.split_once('='))
+            {
+                let limit = limit
+                    .parse()
+                    .map_err(|_| format!("invalid value for --{}", name))?;
+                let limits = &mut options.resource_limits;
+                match name {
+                    "memory-limit" => limits.memory = Some(limit),
+                    "disk-limit" => limits.disk = Some(limit),
+                    "threads" => limits.threads = Some(limit),
+                    _ => return Err(format!("unknown argument {:?}", arg)),
+                }
+                continue;
+            }
+            let value = args
+                .next()
+                .and_then(|value| value.into_string().ok())
+                .ok_or_else(|| format!("missing value for {:?}", arg))?;
+            match arg.to_str() {
+                Some("--backend") => {
+                    options.backend = BackendKind::from_id(&value)
+                        .ok_or_else(|| format!("unknown backend {}", value))?;
+                }
+                Some("--fallback") => options.fallback = FallbackDecoder::from_id(&value),
+                Some("--quality") => {
+                    let quality = value.parse().map_err(|_| "invalid quality".to_string())?;
+                    options.quality = QualityMode::Fixed(quality);
+                }
+                Some("--target-size") => {
+                    let max_bytes = value
+                        .parse()
+                        .map_err(|_| "invalid target size".to_string())?;
+                    target_size = Some(max_bytes);
+                }
+                _ => return Err(format!("unknown argument {:?}", arg)),
+            }
+        }
+        if let Some(max_bytes) = target_size {
+            options.quality = QualityMode::TargetSize {
+                max_bytes,
+                allow_downscale: downscale,
+            };
+        }
+
+        let (Some(input_file), Some(output_file), None) = (args.next(), args.next(), args.peek())
+        else {
+            return Err("expected an input and an output file".to_string());
+        };
+        Ok(Self {
+            input_file: input_file.into(),
+            output_file: output_file.into(),
+            temp_dir,
+            options,
+        })
+    }
+}
+
+/// Entry point of the helper process, returns the exit code.
+pub fn run(args: impl Iterator<Item = OsString>) -> i32 {
+    let request = match Request::parse(args) {
+        Ok(request) => request,
+        Err(e) => {
+            error!("Invalid decode helper arguments: {}", e);
+            return 2;
+        }
+    };
+
+    let mut options = request.options;
+    if let Some(temp_dir) = &request.temp_dir {
+        // E.g. ImageMagick's pixel cache once the memory limit is reached
+        std::env::set_var("MAGICK_TEMPORARY_PATH", temp_dir);
+        std::env::set_var("TMPDIR", temp_dir);
+    }
+    // Before the sandbox, which may keep the backend from reading its configuration
+    backend::apply_resource_limits(&options.resource_limits);
+    if options.sandbox {
+        // heif-convert is a separate program, which the sandbox does not allow to run
+        if options.fallback == Some(FallbackDecoder::HeifConvert) {
+            warn!("heif-convert cannot run inside the sandbox, not using a fallback decoder");
+            options.fallback = None;
+        }
+        if let Err(e) = sandbox::apply(
+            &request.input_file,
+            &request.output_file,
+            request.temp_dir.as_deref(),
+        ) {
+            let result = Err(ConversionError::DecodeFailed {
+                path: request.input_file,
+                reason: e.to_string(),
+            });
+            println!("{}", format_response(&result, &request.output_file));
+            return 0;
+        }
+    }
+
+    let decoders = Decoders::new(&options);
+    let result = conversion::convert_heic_to_jpg(
+        &decoders,
+        request.input_file,
+        request.output_file.clone(),
+        &request.output_file,
+        &options,
+    );
+    println!("{}", format_response(&result, &request.output_file));
+    0
+}
+
+/// Response line: `ok <quality> <decoder> <input size> <output size>` or
+/// `error <kind> <input|output> <reason>`, tab separated.
+fn format_response(result: &Result<ConvertedFile, ConversionError>, output_file: &Path) -> String {
+    let error = match result {
+        Ok(converted) => {
+            return format!(
+                "ok\t{}\t{}\t{}\t{}",
+                converted.quality, converted.decoder, converted.input_size, converted.output_size
+            )
+        }
+        Err(error) => error,
+    };
+
+    let reason = match error {
+        ConversionError::UnreadableInput { reason, .. }
+        | ConversionError::UnsupportedFormat { reason, .. }
+        | ConversionError::DecodeFailed { reason, .. }
+        | ConversionError::EncodeFailed { reason, .. }
+        | ConversionError::Io { reason, .. }
+        | ConversionError::VerificationFailed { reason, .. } => reason.clone(),
+        ConversionError::PermissionDenied { .. } | ConversionError::DiskFull { .. } => {
+            String::new()
+        }
+        // Read back as a decoding failure with the full message
+        error => error.to_string(),
+    };
+    let kind = error.kind();
+    let file = if error.path() == output_file {
+        "output"
+    } else {
+        "input"
+    };
+    // The response has to stay on a single line
+    let reason = reason.replace(['\t', '\n'], " ");
+    format!("error\t{}\t{}\t{}", kind, file, reason)
+}
+
+fn parse_response(
+    line: &str,
+    input_file: PathBuf,
+    output_file: PathBuf,
+) -> Result<ConvertedFile, ConversionError> {
+    let fields: Vec<&str> = line.splitn(5, '\t').collect();
+    match fields.as_slice() {
+        ["ok", quality, decoder, input_size, output_size] => Ok(ConvertedFile {
+            quality: quality.parse().unwrap_or_default(),
+            decoder: decoder_name(decoder),
+            input_size: input_size.parse().unwrap_or_default(),
+            output_size: output_size.parse().unwrap_or_default(),
+        }),
+        ["error", kind, file, reason] => {
+            let path = if *file == "output" {
+                output_file
+            } else {
+                input_file
+            };
+            let reason = reason.to_string();
+            Err(match *kind {
+                "unreadable" => ConversionError::UnreadableInput { path, reason },
+                "unsupported" => ConversionError::UnsupportedFormat { path, reason },
+                "encode" => ConversionError::EncodeFailed { path, reason },
+                "permission" => ConversionError::PermissionDenied { path },
+                "disk-full" => ConversionError::DiskFull { path },
+                "io" => ConversionError::Io { path, reason },
+                "verify" => ConversionError::VerificationFailed { path, reason },
+                _ => ConversionError::DecodeFailed { path, reason },
+            })
+        }
+        _ => Err(ConversionError::DecodeFailed {
+            path: input_file,
+            reason: format!("unexpected response from the decoder process: {:?}", line),
+        }),
+    }
+}
+
+/// Maps a decoder name reported by the helper back to the static name of a known decoder.
+fn decoder_name(name: &str) -> &'static str {
+    FallbackDecoder::all()
+        .into_iter()
+        .map(|fallback| fallback.name())
+        .find(|known| *known == name)
+        .unwrap_or("decoder process")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ResourceLimits;
+    use crate::verification::Verification;
+
+    #[test]
+    fn requests_survive_the_command_line() {
+        let options = ConversionOptions {
+            quality: QualityMode::TargetSize {
+                max_bytes: 500_000,
+                allow_downscale: true,
+            },
+            fallback: None,
+            sandbox: false,
+            sync: true,
+            verify: Some(Verification::Psnr(40.0)),
+            resource_limits: ResourceLimits {
+                memory: Some(256 * 1024 * 1024),
+                disk: None,
+                threads: Some(2),
+            },
+            ..ConversionOptions::default()
+        };
+        let args = helper_args(
+            Path::new("in.heic"),
+            Path::new("-out.jpg"),
+            Path::new("/tmp/.heic2jpg-helper-1"),
+            &options,
+        );
+        let request = Request::parse(args.into_iter()).unwrap();
+
+        assert_eq!(request.input_file, Path::new("in.heic"));
+        assert_eq!(
+            request.temp_dir.as_deref(),
+            Some(Path::new("/tmp/.heic2jpg-helper-1"))
+        );
+        assert_eq!(request.output_file, Path::new("-out.jpg"));
+        let parsed = request.options;
+        assert_eq!(parsed.quality, options.quality);
+        assert_eq!(parsed.fallback, None);
+        assert!(!parsed.sandbox);
+        assert!(parsed.sync);
+        assert_eq!(parsed.verify, options.verify);
+        assert_eq!(parsed.resource_limits.memory, Some(256 * 1024 * 1024));
+        assert_eq!(parsed.resource_limits.disk, None);
+        assert_eq!(parsed.resource_limits.threads, Some(2));
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let parse = |args: &[&str]| Request::parse(args.iter().map(OsString::from));
+        assert!(parse(&["--threads=many", "--", "in", "out"]).is_err());
+        assert!(parse(&["--colors=3", "--", "in", "out"]).is_err());
+        assert!(parse(&["--quality"]).is_err());
+        assert!(parse(&["--", "in"]).is_err());
+        assert!(parse(&["--", "in", "out", "extra"]).is_err());
+    }
+
+    /// Formats `result` as the helper does and reads it back as the parent does.
+    fn round_trip(
+        result: Result<ConvertedFile, ConversionError>,
+    ) -> Result<ConvertedFile, ConversionError> {
+        let response = format_response(&result, Path::new("/tmp/out.jpg"));
+        assert!(!response.contains('\n'), "{:?}", response);
+        parse_response(&response, "/in.heic".into(), "/out.jpg".into())
+    }
+
+    #[test]
+    fn successful_conversions_are_read_back() {
+        let converted = round_trip(Ok(ConvertedFile {
+            quality: 87,
+            decoder: FallbackDecoder::all()[0].name(),
+            input_size: 1234,
+            output_size: 567,
+        }))
+        .unwrap();
+        assert_eq!(converted.quality, 87);
+        assert_eq!(converted.decoder, FallbackDecoder::all()[0].name());
+        assert_eq!(converted.input_size, 1234);
+        assert_eq!(converted.output_size, 567);
+    }
+
+    #[test]
+    fn unknown_decoders_are_named_generically() {
+        let response = "ok\t90\tsomething else\t1\t2";
+        let converted = parse_response(response, "/in.heic".into(), "/out.jpg".into()).unwrap();
+        assert_eq!(converted.decoder, "decoder process");
+    }
+
+    #[test]
+    fn errors_are_read_back_with_their_kind_and_path() {
+        let input = PathBuf::from("/tmp/in.heic");
+        let output = PathBuf::from("/tmp/out.jpg");
+        let errors = [
+            ConversionError::UnreadableInput {
+                path: input.clone(),
+                reason: "short read".to_string(),
+            },
+            ConversionError::UnsupportedFormat {
+                path: input.clone(),
+                reason: "unknown file type".to_string(),
+            },
+            ConversionError::DecodeFailed {
+                path: input.clone(),
+                reason: "broken\ttile\nat 3".to_string(),
+            },
+            ConversionError::EncodeFailed {
+                path: input.clone(),
+                reason: "no memory".to_string(),
+            },
+            ConversionError::PermissionDenied {
+                path: output.clone(),
+            },
+            ConversionError::DiskFull {
+                path: output.clone(),
+            },
+            ConversionError::Io {
+                path: output.clone(),
+                reason: "read-only file system".to_string(),
+            },
+            ConversionError::VerificationFailed {
+                path: output.clone(),
+                reason: "PSNR of 20 dB".to_string(),
+            },
+        ];
+
+        for error in errors {
+            let expected_path = if error.path() == output {
+                Path::new("/out.jpg")
+            } else {
+                Path::new("/in.heic")
+            };
+            let read_back = round_trip(Err(error.clone())).unwrap_err();
+            assert_eq!(read_back.kind(), error.kind());
+            assert_eq!(read_back.path(), expected_path);
+            assert_eq!(
+                read_back.to_string(),
+                error
+                    .to_string()
+                    .replace(
+                        error.path().to_str().unwrap(),
+                        expected_path.to_str().unwrap()
+                    )
+                    .replace(['\t', '\n'], " ")
+            );
+        }
+    }
+
+    #[test]
+    fn garbled_responses_are_decode_failures() {
+        for response in ["", "ok\t90", "maybe\tlater", "error\tio"] {
+            let error = parse_response(response, "/in.heic".into(), "/out.jpg".into()).unwrap_err();
+            assert_eq!(error.kind(), "decode", "{:?}", response);
+            assert_eq!(error.path(), Path::new("/in.heic"));
+        }
+    }
+
+    #[test]
+    fn helpers_running_too_long_are_killed() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let started = Instant::now();
+        assert!(wait(&mut child, Some(Duration::from_millis(100))).is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // Already reaped, so nothing is left running
+        assert!(child.try_wait().unwrap().is_some());
+    }
+
+    #[test]
+    fn helpers_finishing_in_time_report_their_status() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let status = wait(&mut child, Some(Duration::from_secs(10))).unwrap();
+        assert!(status.success());
+
+        let mut child = Command::new("sh")
+            .args(["-c", "kill -SEGV $$"])
+            .spawn()
+            .unwrap();
+        let status = wait(&mut child, None).unwrap();
+        assert_eq!(
+            describe_exit(status),
+            "the decoder process crashed (signal 11)"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+use walkdir::{DirEntry, WalkDir};
+
+/// Rules deciding which files below the input folder get converted.
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    /// Glob patterns a file has to match, relative to the input folder. Empty matches everything.
+    pub include: Vec<String>,
+    /// Glob patterns for files and folders to leave out, relative to the input folder.
+    pub exclude: Vec<String>,
+    /// How deep to descend, `Some(1)` only looks at the input folder itself.
+    pub max_depth: Option<usize>,
+    /// Skip files and folders starting with a dot.
+    pub skip_hidden: bool,
+    pub follow_symlinks: bool,
+    /// Do not descend into folders on other file systems (e.g. mounted network shares).
+    pub same_file_system: bool,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_depth: None,
+            skip_hidden: true,
+            follow_symlinks: true,
+            same_file_system: false,
+        }
+    }
+}
+
+/// Something the directory walk came across.
+#[derive(Debug)]
+pub enum Discovered {
+    /// A heic file matching all rules.
+    File(PathBuf),
+    /// `link` points back to `ancestor`, so it was not followed.
+    SymlinkLoop { link: PathBuf, ancestor: PathBuf },
+}
+
+/// A glob pattern that could not be parsed.
+#[derive(Debug)]
+pub struct InvalidPattern {
+    pub pattern: String,
+    pub reason: String,
+}
+
+/// Walks an input folder according to [`DiscoveryOptions`].
+pub struct Discovery {
+    root: PathBuf,
+    options: DiscoveryOptions,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Discovery {
+    pub fn new(root: PathBuf, options: DiscoveryOptions) -> Result<Self, InvalidPattern> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(&options.include)?)
+        };
+        let exclude = build_glob_set(&options.exclude)?;
+
+        Ok(Self {
+            root,
+            options,
+            include,
+            exclude,
+        })
+    }
+
+    /// The folder the rules are relative to.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn options(&self) -> &DiscoveryOptions {
+        &self.options
+    }
+
+    /// Walks the folder and returns everything of interest in the order it is found.
+    ///
+    /// Stops at the next directory entry once `cancel` is set.
+    pub fn walk(self, cancel: &AtomicBool) -> impl Iterator<Item = Discovered> + '_ {
+        let mut walker = WalkDir::new(&self.root)
+            .follow_links(self.options.follow_symlinks)
+            .same_file_system(self.options.same_file_system);
+        if let Some(max_depth) = self.options.max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+
+        walker
+            .into_iter()
+            .filter_entry(move |entry| self.should_visit(entry))
+            .take_while(|_| !cancel.load(Ordering::Relaxed))
+            .filter_map(|entry| match entry {
+                Ok(entry) => {
+                    let path = entry.path();
+                    if path.is_file() && is_heic(path) {
+                        Some(Discovered::File(entry.into_path()))
+                    } else {
+                        None
+                    }
+                }
+                Err(e) => {
+                    if let (Some(link), Some(ancestor)) = (e.path(), e.loop_ancestor()) {
+                        warn!("Symlink loop: {:?} points to {:?}", link, ancestor);
+                        Some(Discovered::SymlinkLoop {
+                            link: link.to_path_buf(),
+                            ancestor: ancestor.to_path_buf(),
+                        })
+                    } else {
+                        warn!("Unable to read directory entry: {}", e);
+                        None
+                    }
+                }
+            })
+    }
+
+    /// Whether the walk would descend into `folder`, which has to be below the root.
+    ///
+    /// Used to decide which folders created after the walk are watched.
+    pub fn visits_folder(&self, folder: &Path) -> bool {
+        self.accepts(folder, true)
+    }
+
+    /// Whether the walk would return `file` if it existed at that time.
+    pub fn accepts_file(&self, file: &Path) -> bool {
+        is_heic(file) && self.accepts(file, false)
+    }
+
+    /// Checks `path` and all its parents up to the root against the rules.
+    fn accepts(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        if self
+            .options
+            .max_depth
+            .is_some_and(|max_depth| depth > max_depth)
+        {
+            return false;
+        }
+
+        let mut parent = PathBuf::new();
+        for (index, component) in relative.components().enumerate() {
+            parent.push(component);
+            let is_last = index + 1 == depth;
+            if !self.is_visible(&parent, is_dir || !is_last) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Applies the hidden, exclude and include rules. Excluded folders are not descended into.
+    fn should_visit(&self, entry: &DirEntry) -> bool {
+        // Never filter out the folder the user explicitly selected
+        if entry.depth() == 0 {
+            return true;
+        }
+
+        let relative = entry
+            .path()
+            .strip_prefix(&self.root)
+            .unwrap_or(entry.path());
+        self.is_visible(relative, entry.file_type().is_dir())
+    }
+
+    /// The rules for a single entry at `relative` below the root, ignoring its parents.
+    fn is_visible(&self, relative: &Path, is_dir: bool) -> bool {
+        let hidden = relative.file_name().is_some_and(is_hidden);
+        if self.options.skip_hidden && hidden {
+            return false;
+        }
+        if self.exclude.is_match(relative) {
+            return false;
+        }
+        match &self.include {
+            Some(include) if !is_dir => include.is_match(relative),
+            _ => true,
+        }
+    }
+}
+
+/// Whether `path` has the file extension of a HEIC or HEIF image.
+pub fn is_heic(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("heic") || extension.eq_ignore_ascii_case("heif")
+    })
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(b".")
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, InvalidPattern> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| InvalidPattern {
+                pattern: pattern.clone(),
+                reason: e.kind().to_string(),
+            })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| InvalidPattern {
+        pattern: patterns.join(" "),
+        reason: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn discovery(options: DiscoveryOptions) -> Discovery {
+        Discovery::new(PathBuf::from("/photos"), options).unwrap()
+    }
+
+    fn accepts(discovery: &Discovery, file: &str) -> bool {
+        discovery.accepts_file(&Path::new("/photos").join(file))
+    }
+
+    #[test]
+    fn only_heic_files_are_accepted() {
+        let discovery = discovery(DiscoveryOptions::default());
+        assert!(accepts(&discovery, "IMG_0001.heic"));
+        assert!(accepts(&discovery, "2024/IMG_0002.HEIF"));
+        assert!(!accepts(&discovery, "IMG_0003.jpg"));
+        assert!(!accepts(&discovery, "heic"));
+        assert!(!discovery.accepts_file(Path::new("/elsewhere/IMG_0001.heic")));
+    }
+
+    #[test]
+    fn hidden_files_and_folders_are_skipped_unless_asked_for() {
+        let skipping = discovery(DiscoveryOptions::default());
+        assert!(!accepts(&skipping, ".IMG_0001.heic"));
+        assert!(!accepts(&skipping, ".trash/IMG_0001.heic"));
+        assert!(!skipping.visits_folder(Path::new("/photos/2024/.thumbnails")));
+
+        let keeping = discovery(DiscoveryOptions {
+            skip_hidden: false,
+            ..DiscoveryOptions::default()
+        });
+        assert!(accepts(&keeping, ".trash/IMG_0001.heic"));
+        assert!(keeping.visits_folder(Path::new("/photos/2024/.thumbnails")));
+    }
+
+    #[test]
+    fn excluded_folders_are_not_descended_into() {
+        let discovery = discovery(DiscoveryOptions {
+            exclude: vec!["backup".to_string(), "**/*_edited.heic".to_string()],
+            ..DiscoveryOptions::default()
+        });
+        assert!(!discovery.visits_folder(Path::new("/photos/backup")));
+        assert!(!accepts(&discovery, "backup/IMG_0001.heic"));
+        assert!(!accepts(&discovery, "2024/IMG_0001_EDITED.heic"));
+        assert!(accepts(&discovery, "2024/IMG_0001.heic"));
+    }
+
+    #[test]
+    fn include_patterns_only_apply_to_files() {
+        let discovery = discovery(DiscoveryOptions {
+            include: vec!["2024/**".to_string()],
+            ..DiscoveryOptions::default()
+        });
+        assert!(discovery.visits_folder(Path::new("/photos/2023")));
+        assert!(accepts(&discovery, "2024/05/IMG_0001.heic"));
+        assert!(!accepts(&discovery, "2023/IMG_0001.heic"));
+    }
+
+    #[test]
+    fn depth_counts_from_the_input_folder() {
+        let discovery = discovery(DiscoveryOptions {
+            max_depth: Some(2),
+            ..DiscoveryOptions::default()
+        });
+        assert!(accepts(&discovery, "IMG_0001.heic"));
+        assert!(accepts(&discovery, "2024/IMG_0001.heic"));
+        assert!(!accepts(&discovery, "2024/05/IMG_0001.heic"));
+        assert!(discovery.visits_folder(Path::new("/photos/2024")));
+        assert!(!discovery.visits_folder(Path::new("/photos/2024/05/01")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        let error = Discovery::new(
+            PathBuf::from("/photos"),
+            DiscoveryOptions {
+                exclude: vec!["[unclosed".to_string()],
+                ..DiscoveryOptions::default()
+            },
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.pattern, "[unclosed");
+    }
+
+    #[test]
+    fn walk_applies_the_same_rules() {
+        let root = tempfile::tempdir().unwrap();
+        for file in [
+            "IMG_0001.heic",
+            "notes.txt",
+            ".hidden/IMG_0002.heic",
+            "2024/IMG_0003.HEIC",
+            "2024/backup/IMG_0004.heic",
+            "2024/05/deep/IMG_0005.heic",
+        ] {
+            let path = root.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let options = DiscoveryOptions {
+            exclude: vec!["**/backup".to_string()],
+            max_depth: Some(3),
+            ..DiscoveryOptions::default()
+        };
+        let discovery = Discovery::new(root.path().to_path_buf(), options).unwrap();
+
+        let mut found: Vec<_> = discovery
+            .walk(&AtomicBool::new(false))
+            .map(|discovered| match discovered {
+                Discovered::File(path) => path.strip_prefix(root.path()).unwrap().to_path_buf(),
+                Discovered::SymlinkLoop { link, .. } => panic!("unexpected loop at {:?}", link),
+            })
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                PathBuf::from("2024/IMG_0003.HEIC"),
+                PathBuf::from("IMG_0001.heic")
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_walks_stop_at_the_next_entry() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("IMG_0001.heic"), b"").unwrap();
+        let discovery = Discovery::new(root.path().to_path_buf(), DiscoveryOptions::default());
+
+        let cancel = AtomicBool::new(true);
+        assert_eq!(discovery.unwrap().walk(&cancel).count(), 0);
+    }
+}
@@ -0,0 +1,26 @@
+//! The conversion engine behind Heic2JPG, usable without GTK.
+//!
+//! A [`Job`] names input folders or files and an output folder along with its
+//! [`ConversionOptions`]. Running it walks the inputs, converts every HEIC file it finds and
+//! reports progress as [`Event`]s, either to a callback ([`Job::run`]) or over a channel
+//! ([`Job::spawn`]).
+//!
+//! Single images held in memory can be converted with [`convert_blob`], and a [`WatchJob`]
+//! converts new files as they show up in its input folders.
+
+pub mod backend;
+pub mod conversion;
+pub mod decode_helper;
+pub mod discovery;
+pub mod report;
+mod sandbox;
+pub mod verification;
+pub mod watch;
+
+pub use conversion::{
+    convert, convert_blob, ConversionError, ConversionOptions, ConvertedFile, Event, EventSink,
+    Job, ProgressInfo, QualityMode,
+};
+pub use discovery::DiscoveryOptions;
+pub use report::{ConversionReport, FileStatus, ReportFormat};
+pub use watch::WatchJob;
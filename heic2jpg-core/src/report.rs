@@ -0,0 +1,446 @@
+//! Per-file record of a conversion run, exportable as CSV, JSON or HTML.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Converted,
+    /// Not looked at, e.g. a symlink pointing back to one of its parent folders.
+    Skipped,
+    Failed,
+    /// Would have overwritten the output of an earlier file with the same name.
+    Duplicate,
+}
+
+impl FileStatus {
+    const ALL: [FileStatus; 4] = [
+        FileStatus::Converted,
+        FileStatus::Skipped,
+        FileStatus::Failed,
+        FileStatus::Duplicate,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            FileStatus::Converted => "converted",
+            FileStatus::Skipped => "skipped",
+            FileStatus::Failed => "failed",
+            FileStatus::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// What happened to a single discovered file.
+#[derive(Debug, Clone)]
+pub struct ReportEntry {
+    pub input: PathBuf,
+    pub status: FileStatus,
+    pub output: Option<PathBuf>,
+    pub input_size: Option<u64>,
+    pub output_size: Option<u64>,
+    pub duration: Duration,
+    pub quality: Option<u8>,
+    pub decoder: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+impl ReportEntry {
+    /// An entry for `input` with the given status and nothing else filled in yet.
+    pub fn new(input: PathBuf, status: FileStatus) -> Self {
+        Self {
+            input,
+            status,
+            output: None,
+            input_size: None,
+            output_size: None,
+            duration: Duration::ZERO,
+            quality: None,
+            decoder: None,
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+    /// A single HTML page with inline styles.
+    Html,
+}
+
+impl ReportFormat {
+    pub const ALL: [ReportFormat; 3] = [ReportFormat::Html, ReportFormat::Csv, ReportFormat::Json];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Csv => "csv",
+            ReportFormat::Json => "json",
+            ReportFormat::Html => "html",
+        }
+    }
+
+    /// Picks the format matching the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?;
+        Self::ALL.into_iter().find(|format| {
+            extension.eq_ignore_ascii_case(format.extension())
+                || (*format == ReportFormat::Html && extension.eq_ignore_ascii_case("htm"))
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionReport {
+    pub inputs: Vec<PathBuf>,
+    pub output_folder: PathBuf,
+    pub started: SystemTime,
+    pub duration: Duration,
+    pub entries: Vec<ReportEntry>,
+}
+
+impl ConversionReport {
+    pub fn new(inputs: Vec<PathBuf>, output_folder: PathBuf) -> Self {
+        Self {
+            inputs,
+            output_folder,
+            started: SystemTime::now(),
+            duration: Duration::ZERO,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The inputs as a single line, e.g. for headings.
+    pub fn input_names(&self) -> String {
+        let inputs: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|input| input.to_string_lossy())
+            .collect();
+        inputs.join(", ")
+    }
+
+    pub fn count(&self, status: FileStatus) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count()
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Html => self.to_html(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "input,status,output,input_bytes,output_bytes,duration_ms,quality,decoder,error\r\n",
+        );
+        for entry in &self.entries {
+            let fields = [
+                entry.input.to_string_lossy().into_owned(),
+                entry.status.id().to_string(),
+                optional_path(&entry.output),
+                optional(entry.input_size),
+                optional(entry.output_size),
+                entry.duration.as_millis().to_string(),
+                optional(entry.quality),
+                entry.decoder.unwrap_or_default().to_string(),
+                entry.error.clone().unwrap_or_default(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push_str("\r\n");
+        }
+        csv
+    }
+
+    fn to_json(&self) -> String {
+        let report = JsonReport {
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| input.to_string_lossy())
+                .collect(),
+            output_folder: self.output_folder.to_string_lossy(),
+            started: format_timestamp(self.started),
+            duration_ms: self.duration.as_millis(),
+            files: self
+                .entries
+                .iter()
+                .map(|entry| JsonEntry {
+                    input: entry.input.to_string_lossy(),
+                    status: entry.status.id(),
+                    output: entry.output.as_ref().map(|output| output.to_string_lossy()),
+                    input_bytes: entry.input_size,
+                    output_bytes: entry.output_size,
+                    duration_ms: entry.duration.as_millis(),
+                    quality: entry.quality,
+                    decoder: entry.decoder,
+                    error: entry.error.as_deref(),
+                })
+                .collect(),
+        };
+        let mut json =
+            serde_json::to_string_pretty(&report).expect("a report can always be serialized");
+        json.push('\n');
+        json
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Heic2JPG Conversion Report</title>\n<style>\n\
+             body { font-family: sans-serif; margin: 2em; }\n\
+             table { border-collapse: collapse; width: 100%; }\n\
+             th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.6em; text-align: left; }\n\
+             td.number { text-align: right; font-variant-numeric: tabular-nums; }\n\
+             tr.failed { background: #fbe3e4; }\n\
+             tr.duplicate, tr.skipped { background: #fdf6d8; }\n\
+             </style>\n</head>\n<body>\n<h1>Conversion Report</h1>\n",
+        );
+
+        let _ = writeln!(
+            html,
+            "<p>{} → {}<br>Started {}, took {:.1} s</p>",
+            html_escape(&self.input_names()),
+            html_escape(&self.output_folder.to_string_lossy()),
+            format_timestamp(self.started),
+            self.duration.as_secs_f64()
+        );
+        let summary: Vec<String> = FileStatus::ALL
+            .iter()
+            .map(|status| format!("{} {}", self.count(*status), status.id()))
+            .collect();
+        let _ = writeln!(html, "<p>{}</p>", summary.join(", "));
+
+        html.push_str(
+            "<table>\n<tr><th>Input</th><th>Status</th><th>Output</th><th>Input Size</th><th>Output Size</th><th>Time</th><th>Quality</th><th>Decoder</th><th>Error</th></tr>\n",
+        );
+        for entry in &self.entries {
+            let _ = writeln!(
+                html,
+                "<tr class=\"{status}\"><td>{}</td><td>{status}</td><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{} ms</td><td class=\"number\">{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape(&entry.input.to_string_lossy()),
+                html_escape(&optional_path(&entry.output)),
+                optional(entry.input_size),
+                optional(entry.output_size),
+                entry.duration.as_millis(),
+                optional(entry.quality),
+                entry.decoder.unwrap_or_default(),
+                html_escape(entry.error.as_deref().unwrap_or_default()),
+                status = entry.status.id(),
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// Layout of [`ReportFormat::Json`].
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    inputs: Vec<Cow<'a, str>>,
+    output_folder: Cow<'a, str>,
+    started: String,
+    duration_ms: u128,
+    files: Vec<JsonEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    input: Cow<'a, str>,
+    status: &'static str,
+    output: Option<Cow<'a, str>>,
+    input_bytes: Option<u64>,
+    output_bytes: Option<u64>,
+    duration_ms: u128,
+    quality: Option<u8>,
+    decoder: Option<&'static str>,
+    error: Option<&'a str>,
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn optional_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp to the second, e.g. `2024-05-01T12:30:00Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .replace_nanosecond(0)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn timestamp(seconds: u64) -> String {
+        format_timestamp(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        assert_eq!(timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(946684799), "1999-12-31T23:59:59Z");
+        assert_eq!(timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(timestamp(1709209805), "2024-02-29T12:30:05Z");
+        assert_eq!(timestamp(1735689599), "2024-12-31T23:59:59Z");
+        assert_eq!(timestamp(4107542400), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn timestamps_drop_fractions_of_a_second() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_209_805_999);
+        assert_eq!(format_timestamp(time), "2024-02-29T12:30:05Z");
+        let before = UNIX_EPOCH - Duration::from_secs(86400);
+        assert_eq!(format_timestamp(before), "1969-12-31T00:00:00Z");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("IMG_0001.heic"), "IMG_0001.heic");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"cheese\""), "\"say \"\"cheese\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            html_escape("<b>\"Tom & Jerry\"</b>"),
+            "&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;"
+        );
+    }
+
+    fn report() -> ConversionReport {
+        let mut report = ConversionReport::new(
+            vec![PathBuf::from("/in/a,b"), PathBuf::from("/in/c")],
+            PathBuf::from("/out"),
+        );
+        report.started = UNIX_EPOCH + Duration::from_secs(951782400);
+        report.duration = Duration::from_millis(1500);
+
+        let mut converted =
+            ReportEntry::new(PathBuf::from("/in/a,b/1.heic"), FileStatus::Converted);
+        converted.output = Some(PathBuf::from("/out/1.jpg"));
+        converted.input_size = Some(2000);
+        converted.output_size = Some(1000);
+        converted.duration = Duration::from_millis(20);
+        converted.quality = Some(92);
+        converted.decoder = Some("libheif");
+        let mut failed = ReportEntry::new(PathBuf::from("/in/c/\"2\".heic"), FileStatus::Failed);
+        failed.error = Some("broken <file>".to_string());
+        report.entries = vec![converted, failed];
+        report
+    }
+
+    #[test]
+    fn csv_report_lists_every_file() {
+        assert_eq!(
+            report().render(ReportFormat::Csv),
+            "input,status,output,input_bytes,output_bytes,duration_ms,quality,decoder,error\r\n\
+             \"/in/a,b/1.heic\",converted,/out/1.jpg,2000,1000,20,92,libheif,\r\n\
+             \"/in/c/\"\"2\"\".heic\",failed,,,,0,,,broken <file>\r\n"
+        );
+    }
+
+    #[test]
+    fn json_report_lists_every_file() {
+        let json = report().render(ReportFormat::Json);
+        assert!(json.ends_with("}\n"));
+        let report: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            report,
+            json!({
+                "inputs": ["/in/a,b", "/in/c"],
+                "output_folder": "/out",
+                "started": "2000-02-29T00:00:00Z",
+                "duration_ms": 1500,
+                "files": [
+                    {
+                        "input": "/in/a,b/1.heic",
+                        "status": "converted",
+                        "output": "/out/1.jpg",
+                        "input_bytes": 2000,
+                        "output_bytes": 1000,
+                        "duration_ms": 20,
+                        "quality": 92,
+                        "decoder": "libheif",
+                        "error": null
+                    },
+                    {
+                        "input": "/in/c/\"2\".heic",
+                        "status": "failed",
+                        "output": null,
+                        "input_bytes": null,
+                        "output_bytes": null,
+                        "duration_ms": 0,
+                        "quality": null,
+                        "decoder": null,
+                        "error": "broken <file>"
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn html_report_escapes_paths_and_errors() {
+        let html = report().render(ReportFormat::Html);
+        assert!(html
+            .contains("<p>/in/a,b, /in/c → /out<br>Started 2000-02-29T00:00:00Z, took 1.5 s</p>"));
+        assert!(html.contains("<p>1 converted, 0 skipped, 1 failed, 0 duplicate</p>"));
+        assert!(html.contains("<td>/in/c/&quot;2&quot;.heic</td><td>failed</td>"));
+        assert!(html.contains("<td>broken &lt;file&gt;</td>"));
+    }
+
+    #[test]
+    fn formats_are_picked_by_extension() {
+        let format = |path: &str| ReportFormat::from_path(Path::new(path));
+        assert_eq!(format("report.CSV"), Some(ReportFormat::Csv));
+        assert_eq!(format("report.json"), Some(ReportFormat::Json));
+        assert_eq!(format("report.htm"), Some(ReportFormat::Html));
+        assert_eq!(format("report.txt"), None);
+        assert_eq!(format("report"), None);
+    }
+}
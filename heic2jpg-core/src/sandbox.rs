@@ -0,0 +1,143 @@
+//! Locks down the decode helper before it touches untrusted data.
+//!
+//! Landlock limits file system access to reading the input file, reading and writing the output
+//! file and a private temporary folder, and reading the system locations decoders load their plugins
+//! and configuration from. A seccomp filter additionally forbids opening network sockets and
+//! executing other programs.
+
+use landlock::{
+    path_beneath_rules, Access, AccessFs, PathBeneath, PathFd, Ruleset, RulesetAttr,
+    RulesetCreatedAttr, RulesetStatus, ABI,
+};
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, SeccompRule, TargetArch};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Highest Landlock ABI we know about, older kernels enforce what they support.
+const LANDLOCK_ABI: ABI = ABI::V2;
+
+/// Where ImageMagick and libheif load coders, plugins and their configuration from, and where
+/// OpenMP looks up the number of CPUs.
+const SYSTEM_PATHS: &[&str] = &[
+    "/usr",
+    "/lib",
+    "/lib64",
+    "/app",
+    "/etc/ImageMagick-6",
+    "/etc/ImageMagick-7",
+    "/etc/ld.so.cache",
+    "/proc/self",
+    "/sys/devices/system/cpu",
+];
+
+/// Syscalls the helper has no business making.
+const FORBIDDEN_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_accept,
+    libc::SYS_accept4,
+    libc::SYS_execve,
+    libc::SYS_execveat,
+];
+
+#[derive(Debug)]
+pub struct SandboxError(String);
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unable to set up the sandbox: {}", self.0)
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+/// Restricts the current process. Applies to all threads started afterwards as well.
+///
+/// Everything below `temp_dir` stays writable, e.g. for ImageMagick's disk pixel cache.
+pub fn apply(
+    input_file: &Path,
+    output_file: &Path,
+    temp_dir: Option<&Path>,
+) -> Result<(), SandboxError> {
+    restrict_file_system(input_file, output_file, temp_dir)?;
+    forbid_syscalls()
+}
+
+fn restrict_file_system(
+    input_file: &Path,
+    output_file: &Path,
+    temp_dir: Option<&Path>,
+) -> Result<(), SandboxError> {
+    let error = |e: landlock::RulesetError| SandboxError(e.to_string());
+    let file_error = |e: landlock::PathFdError| SandboxError(e.to_string());
+
+    let system_paths = SYSTEM_PATHS.iter().filter(|path| Path::new(path).exists());
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(LANDLOCK_ABI))
+        .map_err(error)?
+        .create()
+        .map_err(error)?
+        .add_rules(path_beneath_rules(
+            system_paths,
+            AccessFs::from_read(LANDLOCK_ABI),
+        ))
+        .map_err(error)?
+        .add_rule(PathBeneath::new(
+            PathFd::new(input_file).map_err(file_error)?,
+            AccessFs::ReadFile,
+        ))
+        .map_err(error)?
+        .add_rule(PathBeneath::new(
+            PathFd::new(output_file).map_err(file_error)?,
+            // Read access to check the written file
+            AccessFs::ReadFile | AccessFs::WriteFile,
+        ))
+        .map_err(error)?
+        .add_rules(path_beneath_rules(
+            temp_dir,
+            AccessFs::from_all(LANDLOCK_ABI),
+        ))
+        .map_err(error)?
+        .restrict_self()
+        .map_err(error)?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => info!("Landlock rules fully enforced"),
+        RulesetStatus::PartiallyEnforced => warn!("Landlock rules only partially enforced"),
+        // Landlock is missing or disabled in the kernel, seccomp still applies
+        RulesetStatus::NotEnforced => warn!("Landlock is not supported by this kernel"),
+    }
+    Ok(())
+}
+
+fn forbid_syscalls() -> Result<(), SandboxError> {
+    let error = |e: seccompiler::BackendError| SandboxError(e.to_string());
+
+    let rules: BTreeMap<i64, Vec<SeccompRule>> = FORBIDDEN_SYSCALLS
+        .iter()
+        .map(|syscall| (*syscall, Vec::new()))
+        .collect();
+    let arch = match TargetArch::try_from(std::env::consts::ARCH) {
+        Ok(arch) => arch,
+        Err(e) => {
+            // Like a kernel without Landlock, the other half of the sandbox still applies
+            warn!("Unable to filter syscalls on this architecture: {}", e);
+            return Ok(());
+        }
+    };
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )
+    .map_err(error)?;
+    let program: BpfProgram = filter.try_into().map_err(error)?;
+
+    seccompiler::apply_filter(&program).map_err(|e| SandboxError(e.to_string()))
+}
@@ -0,0 +1,197 @@
+//! Checks written JPEGs against the image they were encoded from.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::backend::{DecodedImage, ImageBackend};
+
+/// Side length of the windows SSIM is computed over.
+const SSIM_WINDOW: usize = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// How thoroughly a written file is checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verification {
+    /// The output has to decode with the dimensions of the encoded image.
+    Decode,
+    /// Additionally require a minimum PSNR (in dB) against the encoded image.
+    Psnr(f64),
+    /// Additionally require a minimum SSIM (0 to 1) against the encoded image.
+    Ssim(f64),
+}
+
+impl Verification {
+    pub const DEFAULT_MIN_PSNR: f64 = 30.0;
+    pub const DEFAULT_MIN_SSIM: f64 = 0.9;
+}
+
+/// Formats as `decode`, `psnr:<min>` or `ssim:<min>`, which [`FromStr`] reads back.
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verification::Decode => write!(f, "decode"),
+            Verification::Psnr(min) => write!(f, "psnr:{}", min),
+            Verification::Ssim(min) => write!(f, "ssim:{}", min),
+        }
+    }
+}
+
+/// Also accepts `psnr` and `ssim` without a minimum, using the defaults.
+impl FromStr for Verification {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid verification {:?}, expected decode, psnr[:MIN] or ssim[:MIN]",
+                value
+            )
+        };
+        let (kind, min) = match value.split_once(':') {
+            Some((kind, min)) => (kind, Some(min.parse::<f64>().map_err(|_| invalid())?)),
+            None => (value, None),
+        };
+        match kind {
+            "decode" if min.is_none() => Ok(Verification::Decode),
+            "psnr" => Ok(Verification::Psnr(min.unwrap_or(Self::DEFAULT_MIN_PSNR))),
+            "ssim" => Ok(Verification::Ssim(min.unwrap_or(Self::DEFAULT_MIN_SSIM))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Decodes `written` with `backend` and compares it to `source`, the image that was encoded.
+///
+/// Returns why the output does not match.
+pub(crate) fn verify(
+    verification: Verification,
+    backend: &dyn ImageBackend,
+    source: &dyn DecodedImage,
+    written: &[u8],
+) -> Result<(), String> {
+    let output = backend
+        .decode(written)
+        .map_err(|e| format!("the written file does not decode: {}", e))?;
+
+    let (expected, actual) = (source.dimensions(), output.dimensions());
+    if expected != actual {
+        return Err(format!(
+            "the written file is {}x{} pixels instead of {}x{}",
+            actual.0, actual.1, expected.0, expected.1
+        ));
+    }
+
+    let (width, height) = expected;
+    let pixels = |image: &dyn DecodedImage| image.rgb_pixels().map_err(|e| e.to_string());
+    match verification {
+        Verification::Decode => Ok(()),
+        Verification::Psnr(min) => {
+            let value = psnr(&pixels(source)?, &pixels(output.as_ref())?);
+            if value < min {
+                return Err(format!("PSNR of {:.1} dB is below {:.1} dB", value, min));
+            }
+            Ok(())
+        }
+        Verification::Ssim(min) => {
+            let value = ssim(&pixels(source)?, &pixels(output.as_ref())?, width, height);
+            if value < min {
+                return Err(format!("SSIM of {:.3} is below {:.3}", value, min));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Peak signal-to-noise ratio of two RGB images in dB, infinite for identical images.
+fn psnr(a: &[u8], b: &[u8]) -> f64 {
+    let squared_error: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| (f64::from(*a) - f64::from(*b)).powi(2))
+        .sum();
+    let mse = squared_error / a.len().max(1) as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+/// Mean structural similarity of the luma of two RGB images over non-overlapping windows.
+fn ssim(a: &[u8], b: &[u8], width: usize, height: usize) -> f64 {
+    let (a, b) = (luma(a), luma(b));
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for top in (0..height).step_by(SSIM_WINDOW) {
+        for left in (0..width).step_by(SSIM_WINDOW) {
+            let indices: Vec<usize> = (top..(top + SSIM_WINDOW).min(height))
+                .flat_map(|y| (left..(left + SSIM_WINDOW).min(width)).map(move |x| y * width + x))
+                .collect();
+            let count = indices.len() as f64;
+
+            let mean_a = indices.iter().map(|i| a[*i]).sum::<f64>() / count;
+            let mean_b = indices.iter().map(|i| b[*i]).sum::<f64>() / count;
+            let (mut variance_a, mut variance_b, mut covariance) = (0.0, 0.0, 0.0);
+            for i in &indices {
+                let (da, db) = (a[*i] - mean_a, b[*i] - mean_b);
+                variance_a += da * da;
+                variance_b += db * db;
+                covariance += da * db;
+            }
+            variance_a /= count;
+            variance_b /= count;
+            covariance /= count;
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1)
+                    * (variance_a + variance_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        return 1.0;
+    }
+    total / windows as f64
+}
+
+fn luma(rgb: &[u8]) -> Vec<f64> {
+    rgb.chunks_exact(3)
+        .map(|pixel| {
+            0.299 * f64::from(pixel[0]) + 0.587 * f64::from(pixel[1]) + 0.114 * f64::from(pixel[2])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifications_are_parsed() {
+        assert_eq!("decode".parse(), Ok(Verification::Decode));
+        assert_eq!("psnr".parse(), Ok(Verification::Psnr(30.0)));
+        assert_eq!("psnr:42.5".parse(), Ok(Verification::Psnr(42.5)));
+        assert_eq!("ssim".parse(), Ok(Verification::Ssim(0.9)));
+        assert_eq!("ssim:0.95".parse(), Ok(Verification::Ssim(0.95)));
+    }
+
+    #[test]
+    fn invalid_verifications_are_rejected() {
+        for value in ["", "decode:1", "psnr:", "psnr:high", "mse", "PSNR"] {
+            assert!(value.parse::<Verification>().is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn verifications_are_read_back_from_their_display() {
+        for verification in [
+            Verification::Decode,
+            Verification::Psnr(35.25),
+            Verification::Ssim(0.875),
+        ] {
+            assert_eq!(verification.to_string().parse(), Ok(verification));
+        }
+    }
+}
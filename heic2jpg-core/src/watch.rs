@@ -0,0 +1,554 @@
+//! Watching input folders and converting HEIC files as soon as they are written.
+//!
+//! Uses inotify. A file counts as written once it was closed after writing or moved into a
+//! watched folder, and nothing touched it for [`SETTLE_TIME`] afterwards.
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::conversion::{self, ConversionError, ConversionOptions, Decoders, Event, EventSink};
+use crate::discovery::Discovery;
+
+/// Time a file has to stay untouched after it was written before it is converted.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+/// How long to wait for file changes before checking the stop flag and pending files again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Changes we are told about for every watched folder.
+const WATCH_MASK: WatchMask = WatchMask::CLOSE_WRITE
+    .union(WatchMask::MOVED_TO)
+    .union(WatchMask::MODIFY)
+    .union(WatchMask::CREATE);
+
+/// Folders to watch, where to write the converted files and how.
+#[derive(Debug, Clone)]
+pub struct WatchJob {
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub options: ConversionOptions,
+}
+
+impl WatchJob {
+    /// Watches the input folders until `stop` is set and converts new files once they settled.
+    ///
+    /// Files that exist when watching starts are left alone. Sends `ConversionStarted` once
+    /// watching, `FileStarted` and `FileConverted` or `FileFailed` for every new file and
+    /// `ConversionComplete` after stopping, or `ConversionFailed` if a folder cannot be watched.
+    /// Like a batch conversion, a file is skipped with `DuplicateSkipped` if another input of
+    /// the same name was already converted into the output folder.
+    pub fn run(&self, events: &EventSink, stop: &AtomicBool) {
+        match self.watch(events, stop) {
+            Ok(()) => events(Event::ConversionComplete),
+            Err(e) => events(Event::ConversionFailed(e)),
+        }
+    }
+
+    fn watch(&self, events: &EventSink, stop: &AtomicBool) -> Result<(), ConversionError> {
+        let mut roots = Vec::new();
+        for input in &self.inputs {
+            let discovery =
+                Discovery::new(input.clone(), self.options.discovery.clone()).map_err(|e| {
+                    ConversionError::InvalidPattern {
+                        path: input.clone(),
+                        pattern: e.pattern,
+                        reason: e.reason,
+                    }
+                })?;
+            roots.push(discovery);
+        }
+
+        let watch_failed = |path: &Path, e: io::Error| ConversionError::Io {
+            path: path.to_path_buf(),
+            reason: format!("unable to watch the folder: {}", e),
+        };
+        let mut watcher = Watcher::new(roots)
+            .map_err(|e| watch_failed(self.inputs.first().map_or(Path::new("."), |p| p), e))?;
+        for (root, input) in self.inputs.iter().enumerate() {
+            watcher
+                .add_tree(root, input, false, Instant::now())
+                .map_err(|e| watch_failed(input, e))?;
+        }
+        info!("Watching {:?} for new files", self.inputs);
+        events(Event::ConversionStarted);
+
+        let decoders = Decoders::new(&self.options);
+        // Output files written so far and the input they were converted from
+        let mut outputs = HashMap::new();
+        while !stop.load(Ordering::Relaxed) {
+            match watcher.process_events() {
+                Ok(true) => {}
+                Ok(false) => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    warn!("Unable to read file changes: {}", e);
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+            for input_file in watcher.take_settled(Instant::now()) {
+                self.convert(&decoders, input_file, &mut outputs, events);
+            }
+        }
+        info!("Stopped watching {:?}", self.inputs);
+        Ok(())
+    }
+
+    fn convert(
+        &self,
+        decoders: &Decoders,
+        input_file: PathBuf,
+        outputs: &mut HashMap<PathBuf, PathBuf>,
+        events: &EventSink,
+    ) {
+        info!("Converting new file {:?}", input_file);
+        events(Event::FileStarted(input_file.clone()));
+        let output_file = self.output.join(conversion::jpg_file_name(&input_file));
+        if let Some(original) = earlier_input(outputs, &input_file, &output_file) {
+            warn!(
+                "{:?} would overwrite the output of {:?}",
+                input_file, original
+            );
+            events(Event::DuplicateSkipped(input_file, original.clone()));
+            return;
+        }
+        match conversion::convert_file(
+            decoders,
+            input_file.clone(),
+            output_file.clone(),
+            &self.options,
+        ) {
+            Ok(converted) => {
+                outputs.insert(output_file, input_file.clone());
+                events(Event::FileConverted(input_file, converted));
+            }
+            Err(e) => {
+                warn!("{}", e);
+                events(Event::FileFailed(e));
+            }
+        }
+    }
+}
+
+/// The other input that was already converted to `output_file`, if any.
+///
+/// The folders are watched recursively but the output folder is flat. A file written again
+/// replaces its own output, one from another folder must not.
+fn earlier_input<'a>(
+    outputs: &'a HashMap<PathBuf, PathBuf>,
+    input_file: &Path,
+    output_file: &Path,
+) -> Option<&'a PathBuf> {
+    outputs
+        .get(output_file)
+        .filter(|original| *original != input_file)
+}
+
+/// An inotify instance and what its watches stand for.
+struct Watcher {
+    inotify: Inotify,
+    /// The rules of every input folder.
+    roots: Vec<Discovery>,
+    /// Device of every input folder, to stay on it with `same_file_system`.
+    devices: Vec<Option<u64>>,
+    /// Watched folders along with the index of their input folder.
+    folders: HashMap<WatchDescriptor, (usize, PathBuf)>,
+    /// Written files and when they were last touched.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Watcher {
+    fn new(roots: Vec<Discovery>) -> io::Result<Self> {
+        let devices = roots
+            .iter()
+            .map(|root| {
+                fs::metadata(root.root())
+                    .ok()
+                    .map(|metadata| metadata.dev())
+            })
+            .collect();
+        Ok(Self {
+            inotify: Inotify::init()?,
+            roots,
+            devices,
+            folders: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Watches `folder` and every folder below it the rules of `root` allow.
+    ///
+    /// With `pick_up_files`, files already in there are treated as new, e.g. because the
+    /// folder was moved in along with them. Only failing to watch `folder` itself is an error.
+    fn add_tree(
+        &mut self,
+        root: usize,
+        folder: &Path,
+        pick_up_files: bool,
+        now: Instant,
+    ) -> io::Result<()> {
+        self.add_watch(root, folder)?;
+
+        let discovery = &self.roots[root];
+        let options = discovery.options();
+        let entries: Vec<_> = WalkDir::new(folder)
+            .min_depth(1)
+            .follow_links(options.follow_symlinks)
+            .same_file_system(options.same_file_system)
+            .into_iter()
+            .filter_entry(|entry| {
+                !entry.file_type().is_dir() || discovery.visits_folder(entry.path())
+            })
+            .filter_map(Result::ok)
+            .collect();
+        for entry in entries {
+            let is_dir = entry.file_type().is_dir();
+            let path = entry.into_path();
+            if is_dir {
+                if let Err(e) = self.add_watch(root, &path) {
+                    warn!("Unable to watch {:?}: {}", path, e);
+                }
+            } else if pick_up_files && self.roots[root].accepts_file(&path) {
+                self.pending.insert(path, now);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_watch(&mut self, root: usize, folder: &Path) -> io::Result<()> {
+        let watch = self.inotify.watches().add(folder, WATCH_MASK)?;
+        self.folders.insert(watch, (root, folder.to_path_buf()));
+        Ok(())
+    }
+
+    /// Whether `folder` is on the same file system as the input folder, or that does not matter.
+    fn on_root_file_system(&self, root: usize, folder: &Path) -> bool {
+        !self.roots[root].options().same_file_system
+            || fs::metadata(folder).ok().map(|metadata| metadata.dev()) == self.devices[root]
+    }
+
+    /// Takes note of the file changes since the last call and returns whether there were any.
+    fn process_events(&mut self) -> io::Result<bool> {
+        // Large enough for at least one event with the longest possible file name
+        let mut buffer = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(changed),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let now = Instant::now();
+            let mut read_any = false;
+            for event in events {
+                read_any = true;
+                self.handle_event(&event.wd, event.mask, event.name, now);
+            }
+            if !read_any {
+                return Ok(changed);
+            }
+            changed = true;
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        watch: &WatchDescriptor,
+        mask: EventMask,
+        name: Option<&OsStr>,
+        now: Instant,
+    ) {
+        if mask.contains(EventMask::Q_OVERFLOW) {
+            warn!("Too many changes at once, some new files might not be converted");
+            return;
+        }
+        if mask.contains(EventMask::IGNORED) {
+            // The folder was deleted or moved away
+            self.folders.remove(watch);
+            return;
+        }
+        let Some((root, folder)) = self.folders.get(watch).cloned() else {
+            return;
+        };
+        let Some(name) = name.filter(|name| !name.is_empty()) else {
+            return;
+        };
+
+        let path = folder.join(name);
+        let appeared = mask.intersects(EventMask::CREATE | EventMask::MOVED_TO);
+        // A new symlink only counts as a folder if symlinks are followed
+        let is_folder = mask.contains(EventMask::ISDIR)
+            || (appeared
+                && self.roots[root].options().follow_symlinks
+                && path.is_symlink()
+                && path.is_dir());
+        if is_folder {
+            if appeared
+                && self.roots[root].visits_folder(&path)
+                && self.on_root_file_system(root, &path)
+            {
+                if let Err(e) = self.add_tree(root, &path, true, now) {
+                    warn!("Unable to watch {:?}: {}", path, e);
+                }
+            }
+            return;
+        }
+        if !self.roots[root].accepts_file(&path) {
+            return;
+        }
+
+        if mask.intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO) {
+            self.pending.insert(path, now);
+        } else if mask.contains(EventMask::MODIFY) {
+            // Written to again, wait until it is closed and settled once more
+            if let Some(touched) = self.pending.get_mut(&path) {
+                *touched = now;
+            }
+        }
+    }
+
+    /// Removes and returns the files that were not touched for [`SETTLE_TIME`] before `now`.
+    fn take_settled(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, touched)| now.saturating_duration_since(**touched) >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+        settled.sort();
+        for path in &settled {
+            self.pending.remove(path);
+        }
+        // Temporary files are often renamed or deleted right after they were written
+        settled.retain(|path| path.is_file());
+        settled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::DiscoveryOptions;
+
+    /// A watcher on `folder` that has not seen any changes yet.
+    fn watcher(folder: &Path, options: DiscoveryOptions) -> Watcher {
+        let root = Discovery::new(folder.to_path_buf(), options).unwrap();
+        let mut watcher = Watcher::new(vec![root]).unwrap();
+        watcher.add_tree(0, folder, false, Instant::now()).unwrap();
+        watcher
+    }
+
+    /// The watch descriptor of `folder`.
+    fn watch_of(watcher: &Watcher, folder: &Path) -> WatchDescriptor {
+        watcher
+            .folders
+            .iter()
+            .find(|(_, (_, watched))| watched == folder)
+            .map(|(watch, _)| watch.clone())
+            .unwrap()
+    }
+
+    fn watched_folders(watcher: &Watcher) -> Vec<PathBuf> {
+        let mut folders: Vec<_> = watcher
+            .folders
+            .values()
+            .map(|(_, folder)| folder.clone())
+            .collect();
+        folders.sort();
+        folders
+    }
+
+    #[test]
+    fn written_files_are_taken_once_they_settled() {
+        let input = tempfile::tempdir().unwrap();
+        let file = input.path().join("IMG_0001.heic");
+        fs::write(&file, b"heic").unwrap();
+        let mut watcher = watcher(input.path(), DiscoveryOptions::default());
+        let watch = watch_of(&watcher, input.path());
+        let start = Instant::now();
+        let name = Some(OsStr::new("IMG_0001.heic"));
+
+        watcher.handle_event(&watch, EventMask::CLOSE_WRITE, name, start);
+        assert!(watcher.take_settled(start + SETTLE_TIME / 2).is_empty());
+
+        // Written to again before it settled
+        let touched = start + SETTLE_TIME / 2;
+        watcher.handle_event(&watch, EventMask::MODIFY, name, touched);
+        assert!(watcher.take_settled(start + SETTLE_TIME).is_empty());
+        assert_eq!(watcher.take_settled(touched + SETTLE_TIME), [file]);
+        assert!(watcher.take_settled(touched + 2 * SETTLE_TIME).is_empty());
+    }
+
+    #[test]
+    fn only_new_heic_files_are_taken() {
+        let input = tempfile::tempdir().unwrap();
+        for name in ["notes.txt", ".IMG_0002.heic", "IMG_0003.HEIC", "moved.heif"] {
+            fs::write(input.path().join(name), b"heic").unwrap();
+        }
+        let mut watcher = watcher(input.path(), DiscoveryOptions::default());
+        let watch = watch_of(&watcher, input.path());
+        let start = Instant::now();
+
+        for name in ["notes.txt", ".IMG_0002.heic", "IMG_0003.HEIC"] {
+            watcher.handle_event(
+                &watch,
+                EventMask::CLOSE_WRITE,
+                Some(OsStr::new(name)),
+                start,
+            );
+        }
+        watcher.handle_event(
+            &watch,
+            EventMask::MOVED_TO,
+            Some(OsStr::new("moved.heif")),
+            start,
+        );
+        // Only modified, never closed after writing
+        watcher.handle_event(
+            &watch,
+            EventMask::MODIFY,
+            Some(OsStr::new("other.heic")),
+            start,
+        );
+        // Written and deleted again before it settled
+        watcher.handle_event(
+            &watch,
+            EventMask::CLOSE_WRITE,
+            Some(OsStr::new("gone.heic")),
+            start,
+        );
+
+        assert_eq!(
+            watcher.take_settled(start + SETTLE_TIME),
+            [
+                input.path().join("IMG_0003.HEIC"),
+                input.path().join("moved.heif")
+            ]
+        );
+    }
+
+    #[test]
+    fn new_folders_are_watched_with_their_files() {
+        let input = tempfile::tempdir().unwrap();
+        let mut watcher = watcher(
+            input.path(),
+            DiscoveryOptions {
+                exclude: vec!["Trash".to_string()],
+                ..DiscoveryOptions::default()
+            },
+        );
+        let watch = watch_of(&watcher, input.path());
+        let start = Instant::now();
+        for folder in ["2024/05", "Trash"] {
+            fs::create_dir_all(input.path().join(folder)).unwrap();
+            fs::write(input.path().join(folder).join("IMG.heic"), b"heic").unwrap();
+        }
+
+        let folder_created = EventMask::CREATE | EventMask::ISDIR;
+        watcher.handle_event(&watch, folder_created, Some(OsStr::new("2024")), start);
+        watcher.handle_event(&watch, folder_created, Some(OsStr::new("Trash")), start);
+
+        assert_eq!(
+            watched_folders(&watcher),
+            [
+                input.path().to_path_buf(),
+                input.path().join("2024"),
+                input.path().join("2024/05")
+            ]
+        );
+        assert_eq!(
+            watcher.take_settled(start + SETTLE_TIME),
+            [input.path().join("2024/05/IMG.heic")]
+        );
+
+        // Deleted again
+        let watch = watch_of(&watcher, &input.path().join("2024/05"));
+        watcher.handle_event(&watch, EventMask::IGNORED, None, start);
+        assert_eq!(watched_folders(&watcher).len(), 2);
+    }
+
+    #[test]
+    fn symlinked_folders_are_only_watched_when_following_symlinks() {
+        let input = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(elsewhere.path(), input.path().join("link")).unwrap();
+
+        let following = watcher(input.path(), DiscoveryOptions::default());
+        assert_eq!(
+            watched_folders(&following),
+            [input.path().to_path_buf(), input.path().join("link")]
+        );
+
+        let not_following = DiscoveryOptions {
+            follow_symlinks: false,
+            ..DiscoveryOptions::default()
+        };
+        let mut watcher = watcher(input.path(), not_following);
+        assert_eq!(watched_folders(&watcher), [input.path().to_path_buf()]);
+
+        let watch = watch_of(&watcher, input.path());
+        std::os::unix::fs::symlink(elsewhere.path(), input.path().join("new")).unwrap();
+        watcher.handle_event(
+            &watch,
+            EventMask::CREATE,
+            Some(OsStr::new("new")),
+            Instant::now(),
+        );
+        assert_eq!(watched_folders(&watcher), [input.path().to_path_buf()]);
+    }
+
+    #[test]
+    fn other_file_systems_are_left_out_when_asked_to() {
+        let input = tempfile::tempdir().unwrap();
+        let anywhere = watcher(input.path(), DiscoveryOptions::default());
+        assert!(anywhere.on_root_file_system(0, Path::new("/proc")));
+
+        let same_file_system = DiscoveryOptions {
+            same_file_system: true,
+            ..DiscoveryOptions::default()
+        };
+        let watcher = watcher(input.path(), same_file_system);
+        assert!(watcher.on_root_file_system(0, input.path()));
+        assert!(!watcher.on_root_file_system(0, Path::new("/proc")));
+    }
+
+    #[test]
+    fn other_inputs_of_the_same_name_are_duplicates() {
+        let output_file = Path::new("/out/IMG.jpg");
+        let mut outputs = HashMap::new();
+        assert_eq!(
+            earlier_input(&outputs, Path::new("/a/IMG.heic"), output_file),
+            None
+        );
+
+        outputs.insert(output_file.to_path_buf(), PathBuf::from("/a/IMG.heic"));
+        // Written again, so its output is replaced
+        assert_eq!(
+            earlier_input(&outputs, Path::new("/a/IMG.heic"), output_file),
+            None
+        );
+        assert_eq!(
+            earlier_input(&outputs, Path::new("/b/IMG.heic"), output_file),
+            Some(&PathBuf::from("/a/IMG.heic"))
+        );
+    }
+
+    #[test]
+    fn changes_on_disk_are_picked_up() {
+        let input = tempfile::tempdir().unwrap();
+        let mut watcher = watcher(input.path(), DiscoveryOptions::default());
+        assert!(!watcher.process_events().unwrap());
+
+        let file = input.path().join("IMG.heic");
+        fs::write(&file, b"heic").unwrap();
+        assert!(watcher.process_events().unwrap());
+        assert_eq!(watcher.take_settled(Instant::now() + SETTLE_TIME), [file]);
+    }
+}
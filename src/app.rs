@@ -1,57 +1,198 @@
 use crate::config::{APP_ID, PROFILE};
+use crate::dbus::{self, JobState};
 use crate::modals::about::AboutDialog;
-use crate::pages::finished_page::{self, FinishedPage};
+use crate::modals::log_viewer::LogWindow;
+use crate::modals::preferences::PreferencesDialog;
+use crate::pages::finished_page::{self, FinishedPage, FinishedPageInput};
 use crate::pages::progressing_page::{ProgressingPage, ProgressingPageMsg};
-use crate::pages::select_folder::{InOut, SelectFolder, SelectFolderOut};
-use crate::workers::conversion_worker::{
-    ConversionWorker, ConversionWorkerInputMsg, ConversionWorkerMsg,
-};
+use crate::pages::queue_list::{EntryState, QueueEntry, QueueList, QueueListMsg, QueueListOut};
+use crate::pages::select_folder::{self, InOut, SelectFolder, SelectFolderOut};
+use crate::workers::conversion_worker::{ConversionWorker, ConversionWorkerInputMsg};
 use gettextrs::gettext;
 use gtk::prelude::*;
 use gtk::{gio, glib};
+use heic2jpg_core::backend::{BackendKind, FallbackDecoder, ResourceLimits};
+use heic2jpg_core::discovery;
+use heic2jpg_core::verification::Verification;
+use heic2jpg_core::{
+    ConversionError, ConversionOptions, ConversionReport, ConvertedFile, DiscoveryOptions, Event,
+    FileStatus, Job, ProgressInfo, QualityMode, WatchJob,
+};
 use relm4::SimpleComponent;
 use relm4::{
     actions::{RelmAction, RelmActionGroup},
     adw, gtk, main_application, Component, ComponentController, ComponentParts, ComponentSender,
     Controller, WorkerController,
 };
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
 use std::path::PathBuf;
-use tracing::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+thread_local! {
+    /// Files the app was started with, until `App` is initialized.
+    static STARTUP_FILES: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+    static STARTUP_OPEN_HANDLER: Cell<Option<glib::SignalHandlerId>> = const { Cell::new(None) };
+}
+
+/// Handles files passed to the app before its window exists.
+///
+/// A start with files only emits `open` instead of `activate`, which is what creates `App`.
+/// The files are kept for `App::init`, which then handles `open` itself.
+pub(super) fn connect_startup_open(app: &adw::Application) {
+    let handler = app.connect_open(|app, files, _| {
+        STARTUP_FILES.with_borrow_mut(|startup_files| startup_files.extend(local_paths(files)));
+        app.activate();
+    });
+    STARTUP_OPEN_HANDLER.set(Some(handler));
+}
+
+/// The local paths of `files`, the others cannot be converted.
+fn local_paths(files: &[gio::File]) -> Vec<PathBuf> {
+    let paths: Vec<_> = files.iter().filter_map(|file| file.path()).collect();
+    if paths.len() < files.len() {
+        warn!("Ignoring files that are not available locally");
+    }
+    paths
+}
+
+/// How many ended jobs are kept to show them in the queue and answer D-Bus status requests.
+const MAX_FINISHED_JOBS: usize = 20;
 
 enum Mode {
     InputSelection,
     OutputSelection,
     ConversionSelection,
     Progressing,
+    Watching,
     Finished,
     Failed,
 }
 
 pub(super) struct App {
     about_dialog: Controller<AboutDialog>,
+    preferences_dialog: Controller<PreferencesDialog>,
+    log_window: Controller<LogWindow>,
     input_folder_selector: Controller<SelectFolder>,
     output_folder_selector: Controller<SelectFolder>,
     progressing_page: Controller<ProgressingPage>,
     finished_page: Controller<FinishedPage>,
-    input_folder: Option<PathBuf>,
+    queue_list: Controller<QueueList>,
+    /// Shows why e.g. a watch could not be started without leaving the page.
+    toasts: adw::ToastOverlay,
+    /// Folders and files of the job being set up.
+    inputs: Vec<PathBuf>,
     output_folder: Option<PathBuf>,
     conversion_worker: WorkerController<ConversionWorker>,
     mode: Mode,
     failure: Option<String>,
+    /// Id of the running watch and the flag to stop it.
+    watch: Option<(u32, Arc<AtomicBool>)>,
+    /// Files converted since watching started.
+    watch_converted: usize,
+    /// Jobs waiting for the running one, from the window or over D-Bus.
+    queue: VecDeque<QueuedJob>,
+    /// The job the worker is converting.
+    running: Option<QueuedJob>,
+    /// How the last ended jobs went, by id. The oldest are dropped first.
+    finished_jobs: BTreeMap<u32, FinishedJob>,
+    next_job_id: u32,
+    /// Whether the user is setting up another job while one runs.
+    adding_job: bool,
+}
+
+/// A conversion waiting for or being run by the worker.
+struct QueuedJob {
+    id: u32,
+    job: Job,
+    cancel: Arc<AtomicBool>,
+    processed: usize,
+    total: usize,
+    converted: usize,
+    failed: usize,
+    failures: Vec<String>,
+    warnings: Vec<String>,
+    report: Option<ConversionReport>,
+}
+
+impl QueuedJob {
+    fn new(id: u32, job: Job) -> Self {
+        Self {
+            id,
+            job,
+            cancel: Arc::new(AtomicBool::new(false)),
+            processed: 0,
+            total: 0,
+            converted: 0,
+            failed: 0,
+            failures: Vec::new(),
+            warnings: Vec::new(),
+            report: None,
+        }
+    }
+}
+
+/// A job the worker is done with.
+struct FinishedJob {
+    job: QueuedJob,
+    state: JobState,
+    /// Why the job failed as a whole.
+    failure: Option<String>,
+}
+
+impl FinishedJob {
+    /// How the job went in a few words.
+    fn summary(&self) -> String {
+        match self.state {
+            JobState::Failed => self
+                .failure
+                .clone()
+                .unwrap_or_else(|| gettext("The conversion failed")),
+            JobState::Cancelled if self.job.total == 0 => gettext("Cancelled before it started"),
+            JobState::Cancelled => gettext("Cancelled after {processed} of {total} files")
+                .replace("{processed}", &self.job.processed.to_string())
+                .replace("{total}", &self.job.total.to_string()),
+            _ => gettext("{converted} converted, {failed} failed")
+                .replace("{converted}", &self.job.converted.to_string())
+                .replace("{failed}", &self.job.failed.to_string()),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(super) enum AppMsg {
-    InputFolderSelected(PathBuf),
+    /// Files or folders picked on the input page, dropped or passed by the file manager.
+    InputsSelected(Vec<PathBuf>),
     OutputFolderSelected(PathBuf),
     DeselectInputFolder,
     DeselectOutputFolder,
     Convert,
-    ConversionStarted(usize),
-    ProgressUpdate(f64),
-    ConversionComplete,
-    ConversionFailed(String),
+    AddJob,
+    MoveJob(u32, isize),
+    RemoveJob(u32),
+    /// Stops the running job or takes a queued one out of the queue.
+    CancelJob(u32),
+    SaveJobReport(u32),
+    StopWatching,
+    /// The worker started the job or watch with the given id.
+    ConversionStarted(u32),
+    FilesDiscovered(usize),
+    DiscoveryFinished(usize),
+    SymlinkLoop(PathBuf, PathBuf),
+    FileStarted(PathBuf),
+    ProgressUpdate(ProgressInfo),
+    FileConverted(PathBuf, ConvertedFile),
+    FileFailed(ConversionError),
+    DuplicateSkipped(PathBuf, PathBuf),
+    ReportReady(ConversionReport),
+    ConversionComplete(u32),
+    ConversionFailed(u32, ConversionError),
     StartOver,
+    DbusRequest(dbus::Request),
+    ShowLog,
     Quit,
     Noop,
 }
@@ -60,6 +201,7 @@ relm4::new_action_group!(pub(super) WindowActionGroup, "win");
 relm4::new_stateless_action!(PreferencesAction, WindowActionGroup, "preferences");
 relm4::new_stateless_action!(pub(super) ShortcutsAction, WindowActionGroup, "show-help-overlay");
 relm4::new_stateless_action!(AboutAction, WindowActionGroup, "about");
+relm4::new_stateless_action!(ShowLogAction, WindowActionGroup, "show-log");
 
 #[relm4::component(pub)]
 impl SimpleComponent for App {
@@ -73,6 +215,7 @@ impl SimpleComponent for App {
             section! {
                 "_Preferences" => PreferencesAction,
                 "_Keyboard" => ShortcutsAction,
+                "Show _Log" => ShowLogAction,
                 "_About Heic2JPG" => AboutAction,
             }
         }
@@ -110,6 +253,15 @@ impl SimpleComponent for App {
 
             adw::ToolbarView {
                add_top_bar = &adw::HeaderBar {
+                    pack_start = &gtk::Button {
+                        set_icon_name: "go-previous-symbolic",
+                        set_tooltip_text: Some(&gettext("Back to the Queue")),
+                        #[watch]
+                        set_visible: model.adding_job,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AppMsg::StartOver);
+                        }
+                    },
                     #[wrap(Some)]
                     set_title_widget = &adw::WindowTitle {
                         set_title: &gettext("Convert Heic to JPG"),
@@ -120,92 +272,144 @@ impl SimpleComponent for App {
                     }
                 },
 
-                #[transition = "SlideRight"]
-                match model.mode {
-                    Mode::Progressing => {
-                        gtk::Box {
-                            set_vexpand: true,
-                            set_hexpand: true,
-                            append = model.progressing_page.widget(),
-                        }
-                    }
-                    Mode::Finished => {
-                        gtk::Box {
-                            set_vexpand: true,
-                            set_hexpand: true,
-                            append = model.finished_page.widget(),
-                        }
-                    }
-                    Mode::Failed => {
-                        adw::StatusPage {
-                            set_hexpand: true,
-                            set_vexpand: true,
-                            set_title: &gettext("Conversion Failed"),
-                            #[watch]
-                            set_description: model.failure.as_deref(),
-                            set_icon_name: Some("error-outline"),
-
-                            gtk::Box {
-                                set_halign: gtk::Align::Center,
-                                set_orientation: gtk::Orientation::Horizontal,
-                                set_spacing: 24,
-                                gtk::Button {
-                                    set_label: &gettext("Close"),
-                                    connect_clicked[sender] => move |_| {
-                                        sender.input(AppMsg::Quit);
-                                    }
-                                },
-                                gtk::Button {
-                                    set_label: "Restart",
-                                    connect_clicked[sender] => move |_| {
-                                        sender.input(AppMsg::StartOver);
+                #[local_ref]
+                toasts -> adw::ToastOverlay {
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+
+                        #[transition = "SlideRight"]
+                        match model.mode {
+                            Mode::Progressing => {
+                                gtk::Box {
+                                    set_vexpand: true,
+                                    set_hexpand: true,
+                                    set_orientation: gtk::Orientation::Vertical,
+                                    append = model.progressing_page.widget(),
+                                }
+                            }
+                            Mode::Watching => {
+                                adw::StatusPage {
+                                    set_hexpand: true,
+                                    set_vexpand: true,
+                                    set_title: &gettext("Watching for New Files"),
+                                    #[watch]
+                                    set_description: Some(&model.watching_description()),
+                                    set_icon_name: Some("folder-saved-search-symbolic"),
+
+                                    gtk::Button {
+                                        set_halign: gtk::Align::Center,
+                                        set_label: &gettext("Stop Watching"),
+                                        add_css_class: "pill",
+                                        connect_clicked[sender] => move |_| {
+                                            sender.input(AppMsg::StopWatching);
+                                        }
                                     }
                                 }
                             }
-                        }
-                    }
-                    Mode::InputSelection => {
-                        gtk::Box {
-                            set_vexpand: true,
-                            set_hexpand: true,
-                            append = model.input_folder_selector.widget(),
-                        }
-                    }
-                    Mode::OutputSelection => {
-                        gtk::Box {
-                            set_vexpand: true,
-                            set_hexpand: true,
-                            append = model.output_folder_selector.widget(),
-                        }
-                    }
-                    Mode::ConversionSelection => {
-                        adw::StatusPage {
-                            set_hexpand: true,
-                            set_vexpand: true,
-                            set_title: &gettext("Start Conversion"),
-                            set_description: Some(&gettext("Click the button below to start the conversion")),
-                            set_icon_name: Some("blend-tool"),
-
-                            gtk::Box {
-                                set_halign: gtk::Align::Center,
-                                set_orientation: gtk::Orientation::Horizontal,
-                                set_spacing: 24,
-                                gtk::Button {
-                                    set_label: &gettext("Convert"),
-                                    add_css_class: "suggested-action",
-                                    add_css_class: "pill",
-                                    connect_clicked[sender] => move |_| {
-                                        sender.input(AppMsg::Convert);
+                            Mode::Finished => {
+                                gtk::Box {
+                                    set_vexpand: true,
+                                    set_hexpand: true,
+                                    append = model.finished_page.widget(),
+                                }
+                            }
+                            Mode::Failed => {
+                                adw::StatusPage {
+                                    set_hexpand: true,
+                                    set_vexpand: true,
+                                    set_title: &gettext("Conversion Failed"),
+                                    #[watch]
+                                    set_description: model.failure.as_deref(),
+                                    set_icon_name: Some("error-outline"),
+
+                                    gtk::Box {
+                                        set_halign: gtk::Align::Center,
+                                        set_orientation: gtk::Orientation::Horizontal,
+                                        set_spacing: 24,
+                                        gtk::Button {
+                                            set_label: &gettext("Close"),
+                                            connect_clicked[sender] => move |_| {
+                                                sender.input(AppMsg::Quit);
+                                            }
+                                        },
+                                        gtk::Button {
+                                            set_label: &gettext("Show Log"),
+                                            connect_clicked[sender] => move |_| {
+                                                sender.input(AppMsg::ShowLog);
+                                            }
+                                        },
+                                        gtk::Button {
+                                            set_label: "Restart",
+                                            connect_clicked[sender] => move |_| {
+                                                sender.input(AppMsg::StartOver);
+                                            }
+                                        }
                                     }
-                                },
-                                gtk::Button {
-                                    set_label: "Restart",
-                                    connect_clicked[sender] => move |_| {
-                                        sender.input(AppMsg::StartOver);
+                                }
+                            }
+                            Mode::InputSelection => {
+                                gtk::Box {
+                                    set_vexpand: true,
+                                    set_hexpand: true,
+                                    append = model.input_folder_selector.widget(),
+                                }
+                            }
+                            Mode::OutputSelection => {
+                                gtk::Box {
+                                    set_vexpand: true,
+                                    set_hexpand: true,
+                                    append = model.output_folder_selector.widget(),
+                                }
+                            }
+                            Mode::ConversionSelection => {
+                                adw::StatusPage {
+                                    set_hexpand: true,
+                                    set_vexpand: true,
+                                    set_title: &gettext("Start Conversion"),
+                                    set_description: Some(&gettext("Click the button below to start the conversion")),
+                                    set_icon_name: Some("blend-tool"),
+
+                                    gtk::Box {
+                                        set_halign: gtk::Align::Center,
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 24,
+
+                                        #[name = "watch_toggle"]
+                                        gtk::CheckButton {
+                                            set_halign: gtk::Align::Center,
+                                            set_label: Some(&gettext("Keep watching the input folder and convert new files")),
+                                            set_tooltip_text: Some(&gettext("Files already in the folder are left alone")),
+                                        },
+
+                                        gtk::Box {
+                                            set_halign: gtk::Align::Center,
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            set_spacing: 24,
+                                            gtk::Button {
+                                                set_label: &gettext("Convert"),
+                                                add_css_class: "suggested-action",
+                                                add_css_class: "pill",
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(AppMsg::Convert);
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Restart",
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(AppMsg::StartOver);
+                                                }
+                                            }
+                                        }
                                     }
                                 }
                             }
                         }
+
+                        #[local_ref]
+                        queue_widget -> gtk::Box {
+                            #[watch]
+                            set_visible: model.shows_results(),
+                        },
                     }
                 }
             }
@@ -222,11 +426,20 @@ impl SimpleComponent for App {
             .transient_for(&root)
             .launch(())
             .detach();
+        let preferences_dialog = PreferencesDialog::builder()
+            .transient_for(&root)
+            .launch(())
+            .detach();
+        let log_window = LogWindow::builder()
+            .transient_for(&root)
+            .launch(())
+            .detach();
         let input_folder_selector =
             SelectFolder::builder()
                 .launch(InOut::Input)
                 .forward(sender.input_sender(), |msg| match msg {
-                    SelectFolderOut::FolderSelected(path) => AppMsg::InputFolderSelected(path),
+                    SelectFolderOut::FolderSelected(path) => AppMsg::InputsSelected(vec![path]),
+                    SelectFolderOut::InputsSelected(paths) => AppMsg::InputsSelected(paths),
                     SelectFolderOut::AbortLast => AppMsg::Noop,
                 });
         let output_folder_selector =
@@ -234,19 +447,26 @@ impl SimpleComponent for App {
                 .launch(InOut::Output)
                 .forward(sender.input_sender(), |msg| match msg {
                     SelectFolderOut::FolderSelected(path) => AppMsg::OutputFolderSelected(path),
+                    SelectFolderOut::InputsSelected(_) => AppMsg::Noop,
                     SelectFolderOut::AbortLast => AppMsg::DeselectInputFolder,
                 });
-        let conversion_worker =
-            ConversionWorker::builder()
-                .detach_worker(())
-                .forward(sender.input_sender(), |msg| match msg {
-                    ConversionWorkerMsg::ConversionStarted(number) => {
-                        AppMsg::ConversionStarted(number)
-                    }
-                    ConversionWorkerMsg::ProgressUpdate(number) => AppMsg::ProgressUpdate(number),
-                    ConversionWorkerMsg::ConversionComplete => AppMsg::ConversionComplete,
-                    ConversionWorkerMsg::ConversionFailed(e) => AppMsg::ConversionFailed(e),
-                });
+        let conversion_worker = ConversionWorker::builder().detach_worker(()).forward(
+            sender.input_sender(),
+            |(job, msg)| match msg {
+                Event::ConversionStarted => AppMsg::ConversionStarted(job),
+                Event::FilesDiscovered(number) => AppMsg::FilesDiscovered(number),
+                Event::DiscoveryFinished(number) => AppMsg::DiscoveryFinished(number),
+                Event::SymlinkLoop(link, ancestor) => AppMsg::SymlinkLoop(link, ancestor),
+                Event::FileStarted(path) => AppMsg::FileStarted(path),
+                Event::ProgressUpdate(progress) => AppMsg::ProgressUpdate(progress),
+                Event::FileConverted(path, converted) => AppMsg::FileConverted(path, converted),
+                Event::FileFailed(e) => AppMsg::FileFailed(e),
+                Event::DuplicateSkipped(path, original) => AppMsg::DuplicateSkipped(path, original),
+                Event::ReportReady(report) => AppMsg::ReportReady(report),
+                Event::ConversionComplete => AppMsg::ConversionComplete(job),
+                Event::ConversionFailed(e) => AppMsg::ConversionFailed(job, e),
+            },
+        );
 
         let progressing_page = ProgressingPage::builder().launch(()).detach();
         let finished_page =
@@ -257,21 +477,87 @@ impl SimpleComponent for App {
                     finished_page::FinishedPageMsg::Quit => AppMsg::Quit,
                 });
 
+        let queue_list = QueueList::builder()
+            .launch(())
+            .forward(sender.input_sender(), |msg| match msg {
+                QueueListOut::AddJob => AppMsg::AddJob,
+                QueueListOut::MoveUp(id) => AppMsg::MoveJob(id, -1),
+                QueueListOut::MoveDown(id) => AppMsg::MoveJob(id, 1),
+                QueueListOut::Remove(id) => AppMsg::RemoveJob(id),
+                QueueListOut::Cancel(id) => AppMsg::CancelJob(id),
+                QueueListOut::SaveReport(id) => AppMsg::SaveJobReport(id),
+            });
+
         let model = Self {
             about_dialog,
+            preferences_dialog,
+            log_window,
             input_folder_selector,
             output_folder_selector,
             progressing_page,
             finished_page,
+            queue_list,
+            toasts: adw::ToastOverlay::new(),
             conversion_worker,
-            input_folder: None,
+            inputs: Vec::new(),
             output_folder: None,
             mode: Mode::InputSelection,
             failure: None,
+            watch: None,
+            watch_converted: 0,
+            queue: VecDeque::new(),
+            running: None,
+            finished_jobs: BTreeMap::new(),
+            next_job_id: 0,
+            adding_job: false,
         };
 
+        // Take over from the handler that started the app with files
+        if let Some(handler) = STARTUP_OPEN_HANDLER.take() {
+            main_application().disconnect(handler);
+        }
+        let startup_files = STARTUP_FILES.take();
+        if !startup_files.is_empty() {
+            sender.input(AppMsg::InputsSelected(startup_files));
+        }
+        main_application().connect_open({
+            let sender = sender.clone();
+            let window = root.clone();
+            move |_, files, _| {
+                sender.input(AppMsg::InputsSelected(local_paths(files)));
+                window.present();
+            }
+        });
+
+        // Dropping onto any other page starts over with the dropped inputs
+        select_folder::add_drop_target(&root, {
+            let sender = sender.clone();
+            move |paths| {
+                let paths: Vec<_> = paths
+                    .into_iter()
+                    .filter(|path| path.is_dir() || discovery::is_heic(path))
+                    .collect();
+                if paths.is_empty() {
+                    return false;
+                }
+                sender.input(AppMsg::InputsSelected(paths));
+                true
+            }
+        });
+
+        let requests = sender.clone();
+        if let Err(e) = dbus::export(move |request| requests.input(AppMsg::DbusRequest(request))) {
+            warn!("Unable to export the D-Bus interface: {}", e);
+        }
+
+        let toasts = &model.toasts;
+        let queue_widget = model.queue_list.widget();
         let widgets = view_output!();
 
+        gio::Settings::new(APP_ID)
+            .bind("watch-input", &widgets.watch_toggle, "active")
+            .build();
+
         let mut actions = RelmActionGroup::<WindowActionGroup>::new();
 
         let shortcuts_action = {
@@ -288,8 +574,24 @@ impl SimpleComponent for App {
             })
         };
 
+        let preferences_action = {
+            let sender = model.preferences_dialog.sender().clone();
+            RelmAction::<PreferencesAction>::new_stateless(move |_| {
+                sender.send(()).unwrap();
+            })
+        };
+
+        let show_log_action = {
+            let sender = model.log_window.sender().clone();
+            RelmAction::<ShowLogAction>::new_stateless(move |_| {
+                sender.send(()).unwrap();
+            })
+        };
+
         actions.add_action(shortcuts_action);
         actions.add_action(about_action);
+        actions.add_action(preferences_action);
+        actions.add_action(show_log_action);
         actions.register_for_widget(&widgets.main_window);
 
         widgets.load_window_size();
@@ -299,19 +601,52 @@ impl SimpleComponent for App {
 
     fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
         match message {
-            AppMsg::ConversionStarted(number) => {
-                self.mode = Mode::Progressing;
+            // Watching has its own page, there is nothing to show progress for
+            AppMsg::ConversionStarted(id) if self.is_watch(id) => {}
+            AppMsg::ConversionStarted(_) => {
+                // Do not pull the user away from setting up the next job
+                if self.shows_results() {
+                    self.mode = Mode::Progressing;
+                }
+                let _ = self
+                    .progressing_page
+                    .sender()
+                    .send(ProgressingPageMsg::SetScanning(true));
+            }
+            AppMsg::FilesDiscovered(number) => {
                 let _ = self
                     .progressing_page
                     .sender()
                     .send(ProgressingPageMsg::SetFileCount(number));
             }
-            AppMsg::InputFolderSelected(path) => {
-                self.input_folder = Some(path);
-                self.mode = Mode::OutputSelection
+            AppMsg::DiscoveryFinished(number) => {
+                let _ = self
+                    .progressing_page
+                    .sender()
+                    .send(ProgressingPageMsg::SetFileCount(number));
+                let _ = self
+                    .progressing_page
+                    .sender()
+                    .send(ProgressingPageMsg::SetScanning(false));
+            }
+            AppMsg::InputsSelected(paths) => {
+                if self.watch.is_some() {
+                    warn!("Ignoring {:?} while watching", paths);
+                    return;
+                }
+                if paths.is_empty() {
+                    return;
+                }
+                // E.g. dropped onto the progress page
+                if self.running.is_some() {
+                    self.adding_job = true;
+                }
+                info!("Selected {:?}", paths);
+                self.inputs = paths;
+                self.mode = Mode::OutputSelection;
             }
             AppMsg::DeselectInputFolder => {
-                self.input_folder = None;
+                self.inputs.clear();
                 self.mode = Mode::InputSelection
             }
             AppMsg::OutputFolderSelected(path) => {
@@ -324,26 +659,138 @@ impl SimpleComponent for App {
             }
             AppMsg::Quit => main_application().quit(),
             AppMsg::Convert => {
-                if let (Some(input_folder), Some(output_folder)) =
-                    (&self.input_folder, &self.output_folder)
-                {
-                    info!(
-                        "Starting conversion from {:?} to {:?}",
-                        input_folder, output_folder
-                    );
-                    let _ = self.conversion_worker.sender().send(
-                        ConversionWorkerInputMsg::ConvertFolder(
-                            input_folder.clone(),
-                            output_folder.clone(),
-                        ),
-                    );
-                } else {
+                let Some(output_folder) = self
+                    .output_folder
+                    .clone()
+                    .filter(|_| !self.inputs.is_empty())
+                else {
                     self.mode = Mode::Failed;
                     self.failure =
                         Some(gettext("Please select both input and output folders").to_string());
+                    return;
+                };
+
+                if gio::Settings::new(APP_ID).boolean("watch-input") {
+                    // The worker would only get to the watch after the queue and block it
+                    if self.running.is_some() || !self.queue.is_empty() {
+                        self.show_toast(&gettext(
+                            "Folders cannot be watched while other conversions are running or queued",
+                        ));
+                        return;
+                    }
+                    // Single files never change, only folders get new ones
+                    let folders: Vec<_> = self
+                        .inputs
+                        .iter()
+                        .filter(|input| input.is_dir())
+                        .cloned()
+                        .collect();
+                    if folders.is_empty() {
+                        self.show_toast(&gettext("Only folders can be watched for new files"));
+                        return;
+                    }
+                    info!(
+                        "Watching {:?} and converting new files to {:?}",
+                        folders, output_folder
+                    );
+                    let job = WatchJob {
+                        inputs: folders,
+                        output: output_folder,
+                        options: conversion_options(),
+                    };
+                    self.next_job_id += 1;
+                    let id = self.next_job_id;
+                    let stop = Arc::new(AtomicBool::new(false));
+                    self.watch = Some((id, stop.clone()));
+                    self.watch_converted = 0;
+                    self.mode = Mode::Watching;
+                    let _ = self
+                        .conversion_worker
+                        .sender()
+                        .send(ConversionWorkerInputMsg::Watch(id, job, stop));
+                    return;
+                }
+
+                info!(
+                    "Starting conversion from {:?} to {:?}",
+                    self.inputs, output_folder
+                );
+                let job = Job {
+                    inputs: self.inputs.clone(),
+                    output: output_folder,
+                    options: conversion_options(),
+                };
+                self.enqueue(job);
+                self.inputs.clear();
+                self.output_folder = None;
+                self.adding_job = false;
+                if self.running.is_some() {
+                    self.mode = Mode::Progressing;
+                }
+            }
+            AppMsg::AddJob => {
+                self.adding_job = true;
+                self.inputs.clear();
+                self.output_folder = None;
+                self.mode = Mode::InputSelection;
+            }
+            AppMsg::MoveJob(id, offset) => {
+                if let Some(index) = self.queue.iter().position(|queued| queued.id == id) {
+                    let target = index.saturating_add_signed(offset);
+                    if target < self.queue.len() {
+                        self.queue.swap(index, target);
+                        self.update_queue_list();
+                    }
+                }
+            }
+            AppMsg::RemoveJob(id) => {
+                self.remove_queued(id);
+            }
+            AppMsg::CancelJob(id) => {
+                self.cancel_job(id);
+            }
+            AppMsg::SaveJobReport(id) => {
+                if let Some(report) = self
+                    .finished_jobs
+                    .get(&id)
+                    .and_then(|finished| finished.job.report.clone())
+                {
+                    let _ = self
+                        .finished_page
+                        .sender()
+                        .send(FinishedPageInput::SaveJobReport(report));
+                }
+            }
+            AppMsg::StopWatching => {
+                if let Some((_, stop)) = &self.watch {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+            AppMsg::SymlinkLoop(link, _ancestor) => {
+                let warning = gettext("Skipped “{}” because it links to one of its parent folders")
+                    .replace("{}", &link.to_string_lossy());
+                if let Some(job) = &mut self.running {
+                    job.warnings.push(warning.clone());
+                }
+                let _ = self
+                    .finished_page
+                    .sender()
+                    .send(FinishedPageInput::AddWarning(warning));
+            }
+            AppMsg::FileStarted(path) => {
+                if let Mode::Progressing = self.mode {
+                    let _ = self
+                        .progressing_page
+                        .sender()
+                        .send(ProgressingPageMsg::SetCurrentFile(path));
                 }
             }
             AppMsg::ProgressUpdate(progress) => {
+                if let Some(job) = &mut self.running {
+                    job.processed = progress.processed;
+                    job.total = progress.total;
+                    dbus::emit_progress(job.id, job.processed, job.total);
+                }
                 if let Mode::Progressing = self.mode {
                     let _ = self
                         .progressing_page
@@ -351,39 +798,120 @@ impl SimpleComponent for App {
                         .send(ProgressingPageMsg::SetProgress(progress));
                 }
             }
-            AppMsg::ConversionComplete => {
-                self.mode = Mode::Finished;
-                let notification = gio::Notification::new(&gettext("Conversion Complete"));
-                notification.set_body(Some(&gettext("The conversion was successful")));
-                notification.set_category(Some("transfer.complete"));
-                notification.set_priority(gio::NotificationPriority::Normal);
-                let pass_icon = gio::Icon::for_string("test-pass");
-                if let Ok(pass_icon) = pass_icon {
-                    // Ends up as an empty icon on my system. Something is wrong with the icon loading?.
-                    //notification.set_icon(&pass_icon);
+            AppMsg::FileConverted(path, converted) => {
+                info!(
+                    "Converted {:?} with JPEG quality {}, decoded by {}",
+                    path, converted.quality, converted.decoder
+                );
+                if let Mode::Watching = self.mode {
+                    self.watch_converted += 1;
+                }
+            }
+            AppMsg::FileFailed(e) => {
+                let failure = error_message(&e);
+                if let Some(job) = &mut self.running {
+                    job.failures.push(failure.clone());
                 }
+                let _ = self
+                    .finished_page
+                    .sender()
+                    .send(FinishedPageInput::AddFailure(failure));
+            }
+            AppMsg::DuplicateSkipped(path, original) => {
+                let warning = gettext("Skipped “{file}” because “{original}” was already converted to the same file name")
+                    .replace("{file}", &path.to_string_lossy())
+                    .replace("{original}", &original.to_string_lossy());
+                if let Some(job) = &mut self.running {
+                    job.warnings.push(warning.clone());
+                }
+                let _ = self
+                    .finished_page
+                    .sender()
+                    .send(FinishedPageInput::AddWarning(warning));
+            }
+            AppMsg::ReportReady(report) => {
+                if let Some(job) = &mut self.running {
+                    job.converted = report.count(FileStatus::Converted);
+                    job.failed = report.count(FileStatus::Failed);
+                    job.report = Some(report.clone());
+                }
+                let _ = self
+                    .finished_page
+                    .sender()
+                    .send(FinishedPageInput::SetReport(report));
+            }
+            AppMsg::ConversionComplete(id) if self.is_watch(id) => {
+                self.watch = None;
+                if matches!(self.mode, Mode::Watching) || self.shows_results() {
+                    self.mode = Mode::Finished;
+                }
+                self.start_next();
+            }
+            AppMsg::ConversionComplete(id) => {
+                let state = self.finish_running(id, None);
+                if self.shows_results() && self.queue.is_empty() {
+                    self.mode = Mode::Finished;
+                }
+                // A cancelled job completes as well, but was not successful
+                if state == Some(JobState::Completed) {
+                    let notification = gio::Notification::new(&gettext("Conversion Complete"));
+                    notification.set_body(Some(&gettext("The conversion was successful")));
+                    notification.set_category(Some("transfer.complete"));
+                    notification.set_priority(gio::NotificationPriority::Normal);
+                    let pass_icon = gio::Icon::for_string("test-pass");
+                    if let Ok(pass_icon) = pass_icon {
+                        // Ends up as an empty icon on my system. Something is wrong with the icon loading?.
+                        //notification.set_icon(&pass_icon);
+                    }
 
-                let app = relm4::main_application();
-                app.send_notification(None, &notification);
+                    let app = relm4::main_application();
+                    app.send_notification(None, &notification);
+                }
+                self.start_next();
             }
-            AppMsg::ConversionFailed(e) => {
-                self.mode = Mode::Failed;
-                self.failure = Some(e);
+            AppMsg::ConversionFailed(id, e) => {
+                error!("{}", e);
+                let failure = error_message(&e);
+                let was_watching = self.is_watch(id) && matches!(self.mode, Mode::Watching);
+                if self.is_watch(id) {
+                    self.watch = None;
+                } else {
+                    self.finish_running(id, Some(failure.clone()));
+                }
+                if (was_watching || self.shows_results()) && self.queue.is_empty() {
+                    self.mode = Mode::Failed;
+                }
+                self.failure = Some(failure);
+                self.start_next();
+            }
+            AppMsg::StartOver if self.adding_job => {
+                // Back to where the jobs are, without touching them
+                self.adding_job = false;
+                self.inputs.clear();
+                self.output_folder = None;
+                self.mode = if self.running.is_some() {
+                    Mode::Progressing
+                } else if self.failure.is_some() {
+                    Mode::Failed
+                } else {
+                    Mode::Finished
+                };
             }
             AppMsg::StartOver => {
-                self.input_folder = None;
+                self.inputs.clear();
                 self.output_folder = None;
-                let _ = self
-                    .progressing_page
-                    .sender()
-                    .send(ProgressingPageMsg::SetProgress(0.0));
                 self.failure = None;
                 let _ = self
                     .progressing_page
                     .sender()
-                    .send(ProgressingPageMsg::SetFileCount(0));
+                    .send(ProgressingPageMsg::Reset);
+                let _ = self.finished_page.sender().send(FinishedPageInput::Reset);
                 self.mode = Mode::InputSelection;
             }
+            AppMsg::DbusRequest(request) => self.handle_dbus_request(request),
+            AppMsg::ShowLog => {
+                let _ = self.log_window.sender().send(());
+            }
             AppMsg::Noop => {}
         }
     }
@@ -393,6 +921,349 @@ impl SimpleComponent for App {
     }
 }
 
+impl App {
+    /// Queues `job`, starts it right away if nothing else is running and returns its id.
+    fn enqueue(&mut self, job: Job) -> u32 {
+        self.next_job_id += 1;
+        let id = self.next_job_id;
+        self.queue.push_back(QueuedJob::new(id, job));
+        self.start_next();
+        self.update_queue_list();
+        id
+    }
+
+    /// Takes the job `id` out of the queue before it ran. Returns whether it was queued.
+    fn remove_queued(&mut self, id: u32) -> bool {
+        let Some(index) = self.queue.iter().position(|queued| queued.id == id) else {
+            return false;
+        };
+        let Some(job) = self.queue.remove(index) else {
+            return false;
+        };
+        info!("Removed job {} from the queue", id);
+        dbus::emit_job_finished(id, JobState::Cancelled, 0, 0);
+        self.add_finished(FinishedJob {
+            job,
+            state: JobState::Cancelled,
+            failure: None,
+        });
+        self.update_queue_list();
+        true
+    }
+
+    /// Stops the running job `id` or takes it out of the queue. Returns whether it was known.
+    fn cancel_job(&mut self, id: u32) -> bool {
+        if let Some(running) = self.running.as_ref().filter(|running| running.id == id) {
+            // Ends up as cancelled once the worker stopped
+            info!("Cancelling job {}", id);
+            running.cancel.store(true, Ordering::Relaxed);
+            return true;
+        }
+        self.remove_queued(id) || self.finished_jobs.contains_key(&id)
+    }
+
+    /// Keeps how `finished` went, forgetting the oldest jobs beyond `MAX_FINISHED_JOBS`.
+    fn add_finished(&mut self, finished: FinishedJob) {
+        self.finished_jobs.insert(finished.job.id, finished);
+        while self.finished_jobs.len() > MAX_FINISHED_JOBS {
+            self.finished_jobs.pop_first();
+        }
+    }
+
+    fn show_toast(&self, message: &str) {
+        self.toasts.add_toast(adw::Toast::new(message));
+    }
+
+    /// Shows the running job, the queue and then the ended jobs, newest first.
+    fn update_queue_list(&self) {
+        let entry = |job: &QueuedJob, state| QueueEntry {
+            id: job.id,
+            inputs: job.job.inputs.clone(),
+            output: job.job.output.clone(),
+            state,
+        };
+        let running = self
+            .running
+            .iter()
+            .map(|job| entry(job, EntryState::Running));
+        let queued = self.queue.iter().map(|job| entry(job, EntryState::Queued));
+        let finished = self.finished_jobs.values().rev().map(|finished| {
+            let details = finished
+                .job
+                .failures
+                .iter()
+                .chain(&finished.job.warnings)
+                .cloned()
+                .collect();
+            let state = EntryState::Finished {
+                summary: finished.summary(),
+                details,
+                has_report: finished.job.report.is_some(),
+            };
+            entry(&finished.job, state)
+        });
+        let jobs = running.chain(queued).chain(finished).collect();
+        let _ = self.queue_list.sender().send(QueueListMsg::SetJobs(jobs));
+    }
+
+    /// Hands the next queued job to the worker unless it is busy.
+    fn start_next(&mut self) {
+        if self.running.is_some() || self.watch.is_some() {
+            return;
+        }
+        let Some(next) = self.queue.pop_front() else {
+            return;
+        };
+        info!(
+            "Starting job {} from {:?} to {:?}",
+            next.id, next.job.inputs, next.job.output
+        );
+
+        // The pages still show the previous job
+        let _ = self
+            .progressing_page
+            .sender()
+            .send(ProgressingPageMsg::Reset);
+        let _ = self.finished_page.sender().send(FinishedPageInput::Reset);
+        self.failure = None;
+        let _ = self
+            .conversion_worker
+            .sender()
+            .send(ConversionWorkerInputMsg::Convert(
+                next.id,
+                next.job.clone(),
+                next.cancel.clone(),
+            ));
+        self.running = Some(next);
+        self.update_queue_list();
+    }
+
+    /// Records how the running job `id` ended, with `failure` if it failed as a whole, and tells
+    /// D-Bus listeners about it.
+    ///
+    /// Returns the final state of the job, `None` if it was not the running one.
+    fn finish_running(&mut self, id: u32, failure: Option<String>) -> Option<JobState> {
+        if self.running.as_ref().map(|job| job.id) != Some(id) {
+            warn!("Job {} ended but was not running", id);
+            return None;
+        }
+        let job = self.running.take()?;
+        let state = if failure.is_some() {
+            JobState::Failed
+        } else if job.cancel.load(Ordering::Relaxed) {
+            JobState::Cancelled
+        } else {
+            JobState::Completed
+        };
+        info!("Job {} is {}", job.id, state.id());
+        dbus::emit_job_finished(job.id, state, job.converted, job.failed);
+        self.add_finished(FinishedJob {
+            job,
+            state,
+            failure,
+        });
+        self.update_queue_list();
+        Some(state)
+    }
+
+    /// Whether the user is only looking at how the conversions went, so switching between
+    /// those pages does not pull them away from anything else.
+    fn shows_results(&self) -> bool {
+        !self.adding_job && matches!(self.mode, Mode::Progressing | Mode::Finished | Mode::Failed)
+    }
+
+    /// Whether `id` is the folder watch rather than a conversion job.
+    fn is_watch(&self, id: u32) -> bool {
+        self.watch.as_ref().is_some_and(|(watch, _)| *watch == id)
+    }
+
+    /// State of the job `id` and how many of its files were processed out of how many.
+    fn job_status(&self, id: u32) -> Option<(JobState, usize, usize)> {
+        if let Some(job) = self.running.as_ref().filter(|job| job.id == id) {
+            return Some((JobState::Running, job.processed, job.total));
+        }
+        if self.queue.iter().any(|job| job.id == id) {
+            return Some((JobState::Queued, 0, 0));
+        }
+        self.finished_jobs
+            .get(&id)
+            .map(|finished| (finished.state, finished.job.processed, finished.job.total))
+    }
+
+    fn handle_dbus_request(&mut self, request: dbus::Request) {
+        match request {
+            dbus::Request::Queue {
+                input,
+                output,
+                invocation,
+            } => {
+                if !input.is_absolute()
+                    || !input.is_dir()
+                    || !output.is_absolute()
+                    || !output.is_dir()
+                {
+                    dbus::return_invalid_args(
+                        invocation,
+                        "Input and output have to be absolute paths of existing folders",
+                    );
+                    return;
+                }
+                info!(
+                    "Queueing conversion from {:?} to {:?} over D-Bus",
+                    input, output
+                );
+                let id = self.enqueue(Job {
+                    inputs: vec![input],
+                    output,
+                    options: conversion_options(),
+                });
+                invocation.return_value(Some(&(id,).to_variant()));
+            }
+            dbus::Request::Status { job, invocation } => match self.job_status(job) {
+                Some((state, processed, total)) => invocation.return_value(Some(
+                    &(state.id(), processed as u32, total as u32).to_variant(),
+                )),
+                None => dbus::return_unknown_job(invocation, job),
+            },
+            dbus::Request::Cancel { job, invocation } => {
+                if !self.cancel_job(job) {
+                    dbus::return_unknown_job(invocation, job);
+                    return;
+                }
+                invocation.return_value(None);
+            }
+        }
+    }
+
+    /// Tells which folders are watched and how many files were converted so far.
+    fn watching_description(&self) -> String {
+        let folders: Vec<_> = self
+            .inputs
+            .iter()
+            .filter(|input| input.is_dir())
+            .map(|folder| folder.to_string_lossy())
+            .collect();
+        let folder = folders.join("”, “");
+        gettext("New HEIC files in “{folder}” are converted as soon as they are written. {count} converted so far.")
+            .replace("{folder}", &folder)
+            .replace("{count}", &self.watch_converted.to_string())
+    }
+}
+
+/// Turns a conversion error into a message telling the user what went wrong and what to do.
+fn error_message(error: &ConversionError) -> String {
+    let file = error
+        .path()
+        .file_name()
+        .unwrap_or(error.path().as_os_str())
+        .to_string_lossy()
+        .into_owned();
+
+    let message = match error {
+        ConversionError::UnreadableInput { .. } => {
+            gettext("“{}” could not be read. Check that the file still exists and is not damaged.")
+        }
+        ConversionError::UnsupportedFormat { .. } => {
+            gettext("“{}” is not a HEIC image or uses a format that is not supported.")
+        }
+        ConversionError::DecodeFailed { .. } => {
+            gettext("“{}” could not be decoded. The file might be damaged.")
+        }
+        ConversionError::EncodeFailed { .. } => gettext("The JPEG for “{}” could not be created."),
+        ConversionError::PermissionDenied { .. } => gettext(
+            "Access to “{}” was denied. Check the permissions of the input and output folders.",
+        ),
+        ConversionError::DiskFull { .. } => gettext(
+            "There is not enough space left to write “{}”. Free up some space and try again.",
+        ),
+        ConversionError::Io { .. } => gettext("“{}” could not be written."),
+        ConversionError::VerificationFailed { .. } => gettext(
+            "The JPEG written for “{}” is damaged or differs too much from the original. Check the output drive.",
+        ),
+        ConversionError::TimedOut { timeout, .. } => {
+            return gettext("Converting “{file}” took longer than {seconds} seconds.")
+                .replace("{file}", &file)
+                .replace("{seconds}", &timeout.as_secs().to_string());
+        }
+        ConversionError::InvalidPattern { pattern, .. } => {
+            return gettext("The file pattern “{}” is not valid. Check the preferences.")
+                .replace("{}", pattern);
+        }
+    };
+    message.replace("{}", &file)
+}
+
+/// Reads the conversion settings chosen in the preferences.
+fn conversion_options() -> ConversionOptions {
+    let settings = gio::Settings::new(APP_ID);
+
+    let quality = if settings.boolean("target-size-enabled") {
+        QualityMode::TargetSize {
+            max_bytes: settings.int("target-size-kb") as u64 * 1000,
+            allow_downscale: settings.boolean("target-size-downscale"),
+        }
+    } else {
+        QualityMode::Fixed(settings.int("jpeg-quality").clamp(1, 100) as u8)
+    };
+
+    let max_depth = settings.int("discovery-max-depth");
+    let discovery = DiscoveryOptions {
+        include: settings
+            .strv("discovery-include")
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect(),
+        exclude: settings
+            .strv("discovery-exclude")
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect(),
+        max_depth: (max_depth > 0).then_some(max_depth as usize),
+        skip_hidden: settings.boolean("discovery-skip-hidden"),
+        follow_symlinks: settings.boolean("discovery-follow-symlinks"),
+        same_file_system: settings.boolean("discovery-same-file-system"),
+    };
+
+    let backend = BackendKind::from_id(&settings.string("backend")).unwrap_or_default();
+    let fallback = FallbackDecoder::from_id(&settings.string("fallback-decoder"));
+    let timeout = settings.int("file-timeout-seconds");
+
+    ConversionOptions {
+        quality,
+        discovery,
+        backend,
+        fallback,
+        timeout: (timeout > 0).then(|| Duration::from_secs(timeout as u64)),
+        isolate: settings.boolean("isolate-decoding"),
+        sandbox: settings.boolean("sandbox-decoding"),
+        verify: settings.boolean("verify-output").then(|| {
+            match settings.string("verify-metric").as_str() {
+                "psnr" => Verification::Psnr(settings.double("verify-min-psnr")),
+                "ssim" => Verification::Ssim(settings.double("verify-min-ssim")),
+                _ => Verification::Decode,
+            }
+        }),
+        sync: settings.boolean("sync-output"),
+        resource_limits: resource_limits(),
+    }
+}
+
+/// Reads the backend resource limits from the settings, 0 means unlimited.
+pub(super) fn resource_limits() -> ResourceLimits {
+    let settings = gio::Settings::new(APP_ID);
+    let megabytes = |key| {
+        let value = settings.int(key);
+        (value > 0).then(|| value as u64 * 1024 * 1024)
+    };
+    let threads = settings.int("magick-thread-limit");
+
+    ResourceLimits {
+        memory: megabytes("magick-memory-limit-mb"),
+        disk: megabytes("magick-disk-limit-mb"),
+        threads: (threads > 0).then_some(threads as u64),
+    }
+}
+
 impl AppWidgets {
     fn save_window_size(&self) -> Result<(), glib::BoolError> {
         let settings = gio::Settings::new(APP_ID);
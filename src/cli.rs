@@ -0,0 +1,834 @@
+//! Command line mode, used when the first argument is a subcommand like `convert` or `watch`.
+//!
+//! Runs the same conversion as the app but without GTK, so it also works on machines
+//! without a display.
+
+use clap::{Args, Parser, Subcommand};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::VERSION;
+use heic2jpg_core::backend::{self, BackendKind, FallbackDecoder, ResourceLimits};
+use heic2jpg_core::conversion::write_atomically;
+use heic2jpg_core::verification::Verification;
+use heic2jpg_core::{
+    ConversionError, ConversionOptions, ConvertedFile, DiscoveryOptions, Event, EventSink,
+    FileStatus, Job, QualityMode, ReportFormat, WatchJob,
+};
+
+/// Stands for stdin as input and stdout as output.
+const STDIO_PATH: &str = "-";
+
+/// Subcommands that start the command line mode instead of the app.
+const SUBCOMMANDS: &[&str] = &["convert", "watch"];
+
+/// Every file was converted.
+const EXIT_SUCCESS: i32 = 0;
+/// The run finished, but some files could not be converted.
+const EXIT_FILES_FAILED: i32 = 1;
+/// Invalid arguments, as used by clap.
+const EXIT_USAGE: i32 = 2;
+/// The run was aborted, e.g. because of an invalid pattern or a full disk.
+const EXIT_ABORTED: i32 = 3;
+const EXIT_STATUS_HELP: &str = "Exit status: 0 if all files were converted, 1 if some files \
+     failed, 2 for invalid arguments and 3 if the conversion was aborted.";
+
+#[derive(Parser)]
+#[command(name = "heic2jpg", version = VERSION, about = "Convert HEIC images to JPEG")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert all HEIC files in a folder without opening a window
+    ///
+    /// With `-` as input and output, a single image is read from stdin and written to stdout.
+    #[command(after_help = EXIT_STATUS_HELP)]
+    Convert(ConvertArgs),
+    /// Convert HEIC files as soon as they are added to one of the folders
+    ///
+    /// Files already in the folders are left alone. Runs until interrupted.
+    Watch(WatchArgs),
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Folder to search for HEIC files, or `-` to convert a single image from stdin
+    #[arg(
+        long,
+        short,
+        required_unless_present = "input_path",
+        conflicts_with = "input_path"
+    )]
+    input: Option<PathBuf>,
+    /// Folder the JPEG files are written to, or the file (`-` for stdout) when reading stdin
+    #[arg(
+        long,
+        short,
+        required_unless_present = "output_path",
+        conflicts_with = "output_path"
+    )]
+    output: Option<PathBuf>,
+    /// Same as --input
+    #[arg(value_name = "INPUT")]
+    input_path: Option<PathBuf>,
+    /// Same as --output
+    #[arg(value_name = "OUTPUT")]
+    output_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    conversion: ConversionArgs,
+    /// Save a report to this file, as CSV, JSON or HTML depending on the extension
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    #[command(flatten)]
+    output_format: OutputArgs,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// Folders to watch for new HEIC files
+    #[arg(value_name = "FOLDER", required = true)]
+    inputs: Vec<PathBuf>,
+    /// Folder the JPEG files are written to
+    #[arg(long, short)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    conversion: ConversionArgs,
+    #[command(flatten)]
+    output_format: OutputArgs,
+}
+
+/// How files are converted, shared by all subcommands.
+#[derive(Args)]
+struct ConversionArgs {
+    /// JPEG quality from 1 to 100
+    #[arg(long, short, default_value_t = 92, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+    /// Use the highest quality that keeps each file below this size instead of --quality
+    #[arg(long, value_name = "KB")]
+    target_size: Option<u64>,
+    /// Never reduce the image dimensions to reach --target-size
+    #[arg(long, requires = "target_size")]
+    no_downscale: bool,
+
+    /// Image backend used to decode the files
+    #[arg(long, value_parser = parse_backend, default_value = BackendKind::default().id())]
+    backend: BackendKind,
+    /// Decoder to retry with when the backend cannot read a file, libheif by default
+    #[arg(long, value_parser = parse_fallback)]
+    fallback: Option<FallbackDecoder>,
+    /// Do not retry failed files with another decoder
+    #[arg(long, conflicts_with = "fallback")]
+    no_fallback: bool,
+
+    /// Only convert files matching this glob, relative to the input folder (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Leave out files and folders matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// How deep to search, 1 only looks at the input folder itself
+    #[arg(long, value_name = "DEPTH")]
+    max_depth: Option<usize>,
+    /// Also convert hidden files and search hidden folders
+    #[arg(long)]
+    hidden: bool,
+    /// Do not follow symbolic links
+    #[arg(long)]
+    no_follow_symlinks: bool,
+    /// Do not search folders on other file systems
+    #[arg(long)]
+    same_file_system: bool,
+
+    /// Give up on a file after this many seconds (folders only)
+    ///
+    /// Implies --isolate, so the decoder can be stopped once the time is up.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Decode every file in a separate process (folders only)
+    #[arg(long)]
+    isolate: bool,
+    /// Do not sandbox the decoder process
+    #[arg(long)]
+    no_sandbox: bool,
+    /// Memory ImageMagick may use in MB
+    #[arg(long, value_name = "MB")]
+    memory_limit: Option<u64>,
+    /// Disk space ImageMagick may use in MB
+    #[arg(long, value_name = "MB")]
+    disk_limit: Option<u64>,
+    /// Number of threads ImageMagick may use
+    #[arg(long)]
+    threads: Option<u64>,
+
+    /// Check every written file, e.g. --verify=psnr:40: decode (the default), psnr[:MIN] or
+    /// ssim[:MIN]
+    #[arg(
+        long,
+        value_name = "CHECK",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "decode"
+    )]
+    verify: Option<Verification>,
+    /// Flush every written file to disk
+    #[arg(long)]
+    sync: bool,
+}
+
+/// What is printed while converting.
+#[derive(Args)]
+struct OutputArgs {
+    /// Only print failures and the summary
+    #[arg(long, short = 'Q')]
+    quiet: bool,
+    /// Print every event as a JSON object per line to stdout, or with --json=FD to file
+    /// descriptor FD
+    #[arg(long, value_name = "FD", num_args = 0..=1, require_equals = true)]
+    json: Option<Option<u32>>,
+}
+
+/// Returns whether `args` (without the program name) ask for the command line mode.
+pub fn is_cli(args: &[OsString]) -> bool {
+    args.first()
+        .is_some_and(|arg| SUBCOMMANDS.iter().any(|command| arg == command))
+}
+
+/// Runs the command line mode and returns the exit status.
+pub fn run(args: impl IntoIterator<Item = OsString>) -> i32 {
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() {
+                EXIT_USAGE
+            } else {
+                EXIT_SUCCESS
+            };
+        }
+    };
+
+    match cli.command {
+        Command::Convert(args) => convert(args),
+        Command::Watch(args) => watch(args),
+    }
+}
+
+impl ConversionArgs {
+    /// Applies the ImageMagick limits and returns the options to convert with.
+    fn into_options(self) -> ConversionOptions {
+        let resource_limits = ResourceLimits {
+            memory: self.memory_limit.map(|mb| mb * 1024 * 1024),
+            disk: self.disk_limit.map(|mb| mb * 1024 * 1024),
+            threads: self.threads,
+        };
+        backend::apply_resource_limits(&resource_limits);
+
+        ConversionOptions {
+            quality: match self.target_size {
+                Some(kb) => QualityMode::TargetSize {
+                    max_bytes: kb * 1000,
+                    allow_downscale: !self.no_downscale,
+                },
+                None => QualityMode::Fixed(self.quality),
+            },
+            discovery: DiscoveryOptions {
+                include: self.include,
+                exclude: self.exclude,
+                max_depth: self.max_depth,
+                skip_hidden: !self.hidden,
+                follow_symlinks: !self.no_follow_symlinks,
+                same_file_system: self.same_file_system,
+            },
+            backend: self.backend,
+            fallback: if self.no_fallback {
+                None
+            } else {
+                self.fallback.or_else(FallbackDecoder::default_fallback)
+            },
+            timeout: self
+                .timeout
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs),
+            isolate: self.isolate,
+            sandbox: !self.no_sandbox,
+            verify: self.verify,
+            sync: self.sync,
+            resource_limits,
+        }
+    }
+}
+
+impl OutputArgs {
+    /// Opens where the JSON events go, if they were asked for.
+    fn json_writer(&self) -> Result<Option<Box<dyn Write>>, String> {
+        match self.json {
+            // Reopened through /dev/fd, which also tells us whether it is open at all
+            Some(Some(fd)) => OpenOptions::new()
+                .append(true)
+                .open(format!("/dev/fd/{}", fd))
+                .map(|file| Some(Box::new(file) as Box<dyn Write>))
+                .map_err(|e| format!("Unable to write to file descriptor {}: {}", fd, e)),
+            Some(None) => Ok(Some(Box::new(io::stdout()))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn convert(args: ConvertArgs) -> i32 {
+    let (Some(input), Some(output)) = (
+        args.input.or(args.input_path),
+        args.output.or(args.output_path),
+    ) else {
+        unreachable!("clap requires an input and an output");
+    };
+    let from_stdin = input == Path::new(STDIO_PATH);
+    if output == Path::new(STDIO_PATH) && !from_stdin {
+        eprintln!("Only a single image read from stdin can be written to stdout");
+        return EXIT_USAGE;
+    }
+    if from_stdin && output == Path::new(STDIO_PATH) && args.output_format.json == Some(None) {
+        eprintln!("--json needs a file descriptor when the image is written to stdout");
+        return EXIT_USAGE;
+    }
+
+    let mut json = match args.output_format.json_writer() {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let quiet = args.output_format.quiet;
+    let options = args.conversion.into_options();
+
+    if from_stdin {
+        return convert_stdin(&output, &options, json, quiet);
+    }
+
+    // Run the conversion in the background and print its events as they come in
+    let job = Job {
+        inputs: vec![input],
+        output,
+        options,
+    };
+    let (conversion, events) = job.spawn();
+
+    let mut total = 0;
+    let mut exit_code = EXIT_SUCCESS;
+    for event in events {
+        write_json(&mut json, &event);
+
+        match event {
+            Event::ConversionStarted => {}
+            Event::FilesDiscovered(count) => total = count,
+            Event::DiscoveryFinished(count) => {
+                total = count;
+                if !quiet {
+                    eprintln!("Found {} HEIC files", count);
+                }
+            }
+            Event::ProgressUpdate(progress) => {
+                total = total.max(progress.total);
+            }
+            Event::ReportReady(report) => {
+                eprintln!(
+                    "{} of {} files converted, {} failed, {} skipped in {:.1} s",
+                    report.count(FileStatus::Converted),
+                    total,
+                    report.count(FileStatus::Failed),
+                    report.count(FileStatus::Skipped) + report.count(FileStatus::Duplicate),
+                    report.duration.as_secs_f64()
+                );
+                if report.count(FileStatus::Failed) > 0 {
+                    exit_code = EXIT_FILES_FAILED;
+                }
+
+                if let Some(path) = &args.report {
+                    let format = ReportFormat::from_path(path).unwrap_or(ReportFormat::Json);
+                    if let Err(e) = std::fs::write(path, report.render(format)) {
+                        eprintln!("Unable to save the report to {:?}: {}", path, e);
+                    }
+                }
+            }
+            Event::ConversionFailed(e) => {
+                eprintln!("Conversion aborted: {}", e);
+                exit_code = EXIT_ABORTED;
+            }
+            event => print_file_event(&event, quiet),
+        }
+    }
+
+    let _ = conversion.join();
+    exit_code
+}
+
+fn watch(args: WatchArgs) -> i32 {
+    let mut json = match args.output_format.json_writer() {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let quiet = args.output_format.quiet;
+    let job = WatchJob {
+        inputs: args.inputs,
+        output: args.output,
+        options: args.conversion.into_options(),
+    };
+
+    let (sender, events) = mpsc::channel();
+    let watcher = thread::spawn(move || {
+        let sink: EventSink = Arc::new(move |event| {
+            let _ = sender.send(event);
+        });
+        // Never stopped, the process is simply interrupted
+        job.run(&sink, &AtomicBool::new(false));
+    });
+
+    let mut exit_code = EXIT_SUCCESS;
+    for event in events {
+        write_json(&mut json, &event);
+
+        match event {
+            Event::ConversionStarted => {
+                if !quiet {
+                    eprintln!("Watching for new HEIC files, press Ctrl+C to stop");
+                }
+            }
+            Event::ConversionFailed(e) => {
+                eprintln!("Watching failed: {}", e);
+                exit_code = EXIT_ABORTED;
+            }
+            event => print_file_event(&event, quiet),
+        }
+    }
+
+    let _ = watcher.join();
+    exit_code
+}
+
+/// Prints what happened to a single file to stderr.
+fn print_file_event(event: &Event, quiet: bool) {
+    match event {
+        Event::SymlinkLoop(link, ancestor) => {
+            eprintln!(
+                "Skipped {:?}: links to its parent folder {:?}",
+                link, ancestor
+            );
+        }
+        Event::FileConverted(path, converted) if !quiet => {
+            eprintln!(
+                "Converted {:?} (quality {}, decoded by {})",
+                path, converted.quality, converted.decoder
+            );
+        }
+        Event::FileFailed(e) => eprintln!("Failed: {}", e),
+        Event::DuplicateSkipped(path, original) => {
+            eprintln!(
+                "Skipped {:?}: {:?} was already converted to the same file name",
+                path, original
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Converts a single image from stdin and writes it to `output`, which may be stdout.
+fn convert_stdin(
+    output: &Path,
+    options: &ConversionOptions,
+    json: Option<Box<dyn Write>>,
+    quiet: bool,
+) -> i32 {
+    convert_stream(
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+        output,
+        options.sync,
+        json,
+        quiet,
+        |name, data| heic2jpg_core::convert_blob(name, data, options),
+    )
+}
+
+/// Converts the image read from `input` with `convert` and writes it to `output`, or to
+/// `stdout` if `output` is `-`. `sync` flushes the written file to disk.
+fn convert_stream(
+    input: &mut dyn Read,
+    stdout: &mut dyn Write,
+    output: &Path,
+    sync: bool,
+    mut json: Option<Box<dyn Write>>,
+    quiet: bool,
+    convert: impl FnOnce(&Path, &[u8]) -> Result<(Vec<u8>, ConvertedFile), ConversionError>,
+) -> i32 {
+    write_json(&mut json, &Event::ConversionStarted);
+
+    let mut data = Vec::new();
+    if let Err(e) = input.read_to_end(&mut data) {
+        eprintln!("Unable to read stdin: {}", e);
+        let e = ConversionError::UnreadableInput {
+            path: PathBuf::from(STDIO_PATH),
+            reason: e.to_string(),
+        };
+        write_json(&mut json, &Event::ConversionFailed(e));
+        return EXIT_ABORTED;
+    }
+
+    let name = Path::new(STDIO_PATH);
+    let result = convert(name, &data).and_then(|(jpeg, converted)| {
+        let write_error = |e: io::Error| ConversionError::Io {
+            path: output.to_path_buf(),
+            reason: e.to_string(),
+        };
+        if output == name {
+            stdout
+                .write_all(&jpeg)
+                .and_then(|_| stdout.flush())
+                .map_err(write_error)?;
+        } else {
+            // Never leave a partial image behind, just like folder conversions
+            write_atomically(output, sync, |temp_file| {
+                fs::write(temp_file, &jpeg).map_err(write_error)
+            })?;
+        }
+        Ok(converted)
+    });
+
+    let (event, exit_code) = match result {
+        Ok(converted) => {
+            if !quiet {
+                eprintln!(
+                    "Converted {} to {} bytes (quality {}, decoded by {})",
+                    converted.input_size,
+                    converted.output_size,
+                    converted.quality,
+                    converted.decoder
+                );
+            }
+            (
+                Event::FileConverted(name.to_path_buf(), converted),
+                EXIT_SUCCESS,
+            )
+        }
+        Err(e) => {
+            eprintln!("Failed: {}", e);
+            (Event::FileFailed(e), EXIT_FILES_FAILED)
+        }
+    };
+    write_json(&mut json, &event);
+    write_json(&mut json, &Event::ConversionComplete);
+    exit_code
+}
+
+/// Prints `event` as a JSON line, if the events were asked for.
+fn write_json(json: &mut Option<Box<dyn Write>>, event: &Event) {
+    if let Some(json) = json {
+        let _ = writeln!(json, "{}", event.to_json()).and_then(|_| json.flush());
+    }
+}
+
+fn parse_backend(id: &str) -> Result<BackendKind, String> {
+    BackendKind::from_id(id).ok_or_else(|| {
+        let ids: Vec<_> = BackendKind::ALL.iter().map(|kind| kind.id()).collect();
+        format!("expected one of {}", ids.join(", "))
+    })
+}
+
+fn parse_fallback(id: &str) -> Result<FallbackDecoder, String> {
+    FallbackDecoder::from_id(id).ok_or_else(|| {
+        let ids: Vec<_> = FallbackDecoder::all()
+            .into_iter()
+            .map(|fallback| fallback.id())
+            .collect();
+        format!("expected one of {}", ids.join(", "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["heic2jpg"].iter().chain(args))
+    }
+
+    /// Parses `convert` with `args`.
+    fn convert_args(args: &[&str]) -> ConvertArgs {
+        let args: Vec<_> = ["convert"].iter().chain(args).copied().collect();
+        match parse(&args).unwrap().command {
+            Command::Convert(args) => args,
+            Command::Watch(_) => panic!("expected convert"),
+        }
+    }
+
+    fn run_with(args: &[&str]) -> i32 {
+        run(["heic2jpg"].iter().chain(args).map(OsString::from))
+    }
+
+    #[test]
+    fn only_subcommands_start_the_command_line() {
+        assert!(is_cli(&["convert".into()]));
+        assert!(is_cli(&["watch".into(), "/photos".into()]));
+        assert!(!is_cli(&[]));
+        assert!(!is_cli(&["/photos/IMG_0001.heic".into()]));
+    }
+
+    #[test]
+    fn folders_can_be_given_as_options_or_positionally() {
+        let args = convert_args(&["-i", "/in", "-o", "/out"]);
+        assert_eq!(args.input.as_deref(), Some(Path::new("/in")));
+        assert_eq!(args.output.as_deref(), Some(Path::new("/out")));
+
+        let args = convert_args(&["/in", "/out"]);
+        assert_eq!(args.input_path.as_deref(), Some(Path::new("/in")));
+        assert_eq!(args.output_path.as_deref(), Some(Path::new("/out")));
+
+        assert!(parse(&["convert", "/in"]).is_err());
+        assert!(parse(&["convert", "-i", "/in", "/in", "/out"]).is_err());
+    }
+
+    #[test]
+    fn conversion_options_follow_the_arguments() {
+        let options = convert_args(&["/in", "/out"]).conversion.into_options();
+        assert!(matches!(options.quality, QualityMode::Fixed(92)));
+        assert!(options.discovery.follow_symlinks);
+        assert!(options.sandbox);
+        assert_eq!(options.timeout, None);
+
+        let options = convert_args(&[
+            "/in",
+            "/out",
+            "--target-size=500",
+            "--no-downscale",
+            "--timeout=0",
+            "--no-follow-symlinks",
+            "--exclude=Trash",
+            "--exclude=My Photos/*",
+        ])
+        .conversion
+        .into_options();
+        assert!(matches!(
+            options.quality,
+            QualityMode::TargetSize {
+                max_bytes: 500_000,
+                allow_downscale: false
+            }
+        ));
+        assert_eq!(options.timeout, None);
+        assert!(!options.discovery.follow_symlinks);
+        assert_eq!(options.discovery.exclude, ["Trash", "My Photos/*"]);
+
+        let options = convert_args(&["/in", "/out", "--timeout", "30"])
+            .conversion
+            .into_options();
+        assert_eq!(options.timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(parse(&["convert", "/in", "/out", "--quality=0"]).is_err());
+        assert!(parse(&["convert", "/in", "/out", "--quality=101"]).is_err());
+        assert!(parse(&["convert", "/in", "/out", "--backend=gimp"]).is_err());
+        assert!(parse(&["convert", "/in", "/out", "--no-downscale"]).is_err());
+        assert!(parse(&[
+            "convert",
+            "/in",
+            "/out",
+            "--fallback=libheif",
+            "--no-fallback"
+        ])
+        .is_err());
+        assert!(parse(&["watch", "-o", "/out"]).is_err());
+    }
+
+    #[test]
+    fn optional_values_need_an_equals_sign() {
+        let args = convert_args(&["/in", "/out", "--json", "--verify"]);
+        assert_eq!(args.output_format.json, Some(None));
+        assert!(args.conversion.verify.is_some());
+
+        let args = convert_args(&["/in", "/out", "--json=3"]);
+        assert_eq!(args.output_format.json, Some(Some(3)));
+
+        // Otherwise the folder would be taken as the value
+        let args = convert_args(&["--json", "/in", "/out"]);
+        assert_eq!(args.output_format.json, Some(None));
+        assert_eq!(args.output_path.as_deref(), Some(Path::new("/out")));
+    }
+
+    #[test]
+    fn exit_status_is_0_when_everything_was_converted() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let (input, output) = (
+            input.path().to_str().unwrap(),
+            output.path().to_str().unwrap(),
+        );
+
+        assert_eq!(run_with(&["convert", input, output, "-Q"]), EXIT_SUCCESS);
+        assert_eq!(run_with(&["convert", "--help"]), EXIT_SUCCESS);
+    }
+
+    #[test]
+    fn exit_status_is_1_when_files_failed() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("IMG_0001.heic"), b"not an image").unwrap();
+        let (input, output) = (
+            input.path().to_str().unwrap(),
+            output.path().to_str().unwrap(),
+        );
+
+        assert_eq!(
+            run_with(&["convert", input, output, "-Q"]),
+            EXIT_FILES_FAILED
+        );
+    }
+
+    #[test]
+    fn exit_status_is_2_for_invalid_arguments() {
+        assert_eq!(run_with(&["convert"]), EXIT_USAGE);
+        assert_eq!(run_with(&["convert", "/in", "-"]), EXIT_USAGE);
+        assert_eq!(run_with(&["convert", "-", "-", "--json"]), EXIT_USAGE);
+    }
+
+    #[test]
+    fn exit_status_is_3_when_the_conversion_was_aborted() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let (input, output) = (
+            input.path().to_str().unwrap(),
+            output.path().to_str().unwrap(),
+        );
+
+        assert_eq!(
+            run_with(&["convert", input, output, "--include=[", "-Q"]),
+            EXIT_ABORTED
+        );
+    }
+
+    /// Runs [`convert_stream`] on `input` and returns the exit status, what was written to
+    /// stdout and the JSON events.
+    fn convert_stream_with(
+        input: &[u8],
+        output: &Path,
+        convert: impl FnOnce(&Path, &[u8]) -> Result<(Vec<u8>, ConvertedFile), ConversionError>,
+    ) -> (i32, Vec<u8>, Vec<serde_json::Value>) {
+        let events = tempfile::NamedTempFile::new().unwrap();
+        let json = Box::new(events.reopen().unwrap());
+        let mut stdout = Vec::new();
+        let exit_code = convert_stream(
+            &mut &input[..],
+            &mut stdout,
+            output,
+            false,
+            Some(json),
+            true,
+            convert,
+        );
+
+        let events = fs::read_to_string(events.path()).unwrap();
+        let events = events
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (exit_code, stdout, events)
+    }
+
+    fn event_names(events: &[serde_json::Value]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn stdin_conversions_are_wrapped_in_job_events() {
+        let (exit_code, stdout, events) =
+            convert_stream_with(b"not an image", Path::new(STDIO_PATH), |name, data| {
+                heic2jpg_core::convert_blob(name, data, &ConversionOptions::default())
+            });
+
+        assert_eq!(exit_code, EXIT_FILES_FAILED);
+        assert!(stdout.is_empty());
+        assert_eq!(
+            event_names(&events),
+            ["job_started", "file_failed", "job_finished"]
+        );
+        assert_eq!(events[1]["input"], STDIO_PATH);
+        assert_eq!(events[2]["aborted"], false);
+    }
+
+    /// Stands in for the decoder, every image is converted to its reversed bytes.
+    fn reverse(_name: &Path, data: &[u8]) -> Result<(Vec<u8>, ConvertedFile), ConversionError> {
+        let jpeg: Vec<u8> = data.iter().rev().copied().collect();
+        let converted = ConvertedFile {
+            quality: 92,
+            decoder: "reverse",
+            input_size: data.len() as u64,
+            output_size: jpeg.len() as u64,
+        };
+        Ok((jpeg, converted))
+    }
+
+    #[test]
+    fn stdin_is_converted_to_stdout() {
+        let (exit_code, stdout, events) =
+            convert_stream_with(b"heic", Path::new(STDIO_PATH), reverse);
+
+        assert_eq!(exit_code, EXIT_SUCCESS);
+        assert_eq!(stdout, b"cieh");
+        assert_eq!(
+            event_names(&events),
+            ["job_started", "file_done", "job_finished"]
+        );
+        assert_eq!(events[1]["output_bytes"], 4);
+    }
+
+    #[test]
+    fn stdin_is_converted_to_a_file() {
+        let output = tempfile::tempdir().unwrap();
+        let output_file = output.path().join("IMG.jpg");
+        fs::write(&output_file, b"old").unwrap();
+
+        let (exit_code, stdout, _) = convert_stream_with(b"heic", &output_file, reverse);
+
+        assert_eq!(exit_code, EXIT_SUCCESS);
+        assert!(stdout.is_empty());
+        assert_eq!(fs::read(&output_file).unwrap(), b"cieh");
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_stdin_conversions_leave_the_output_file_alone() {
+        let output = tempfile::tempdir().unwrap();
+        let output_file = output.path().join("IMG.jpg");
+
+        let (exit_code, _, _) = convert_stream_with(b"heic", &output_file, |name, _| {
+            Err(ConversionError::DecodeFailed {
+                path: name.to_path_buf(),
+                reason: "broken".to_string(),
+            })
+        });
+        assert_eq!(exit_code, EXIT_FILES_FAILED);
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 0);
+
+        let missing = output.path().join("missing").join("IMG.jpg");
+        let (exit_code, _, events) = convert_stream_with(b"heic", &missing, reverse);
+        assert_eq!(exit_code, EXIT_FILES_FAILED);
+        assert_eq!(events[1]["event"], "file_failed");
+    }
+}
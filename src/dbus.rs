@@ -0,0 +1,172 @@
+//! The `dev.nordgedanken.heic2jpg.Converter` D-Bus interface, which lets other programs queue
+//! conversions in the running app.
+//!
+//! It is exported on the app's object path, e.g. `/dev/nordgedanken/heic2jpg`. Jobs run one
+//! after another on the same worker as the ones started in the window.
+
+use relm4::gtk::prelude::*;
+use relm4::gtk::{gio, glib};
+use relm4::main_application;
+use std::path::PathBuf;
+use tracing::warn;
+
+pub const INTERFACE_NAME: &str = "dev.nordgedanken.heic2jpg.Converter";
+/// Returned for job ids that were never handed out.
+const UNKNOWN_JOB_ERROR: &str = "dev.nordgedanken.heic2jpg.Converter.Error.UnknownJob";
+const INVALID_ARGS_ERROR: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="dev.nordgedanken.heic2jpg.Converter">
+    <!-- Converts every HEIC file below input into the output folder, with the current settings -->
+    <method name="Queue">
+      <arg name="input" type="s" direction="in"/>
+      <arg name="output" type="s" direction="in"/>
+      <arg name="job" type="u" direction="out"/>
+    </method>
+    <!-- One of queued, running, completed, failed or cancelled, and the files done so far -->
+    <method name="GetStatus">
+      <arg name="job" type="u" direction="in"/>
+      <arg name="state" type="s" direction="out"/>
+      <arg name="processed" type="u" direction="out"/>
+      <arg name="total" type="u" direction="out"/>
+    </method>
+    <!-- Removes a queued job, or stops a running one before its next file -->
+    <method name="Cancel">
+      <arg name="job" type="u" direction="in"/>
+    </method>
+    <signal name="Progress">
+      <arg name="job" type="u"/>
+      <arg name="processed" type="u"/>
+      <arg name="total" type="u"/>
+    </signal>
+    <signal name="JobFinished">
+      <arg name="job" type="u"/>
+      <arg name="state" type="s"/>
+      <arg name="converted" type="u"/>
+      <arg name="failed" type="u"/>
+    </signal>
+  </interface>
+</node>
+"#;
+
+/// A method call, answered by the app through `invocation`.
+#[derive(Debug)]
+pub enum Request {
+    Queue {
+        input: PathBuf,
+        output: PathBuf,
+        invocation: gio::DBusMethodInvocation,
+    },
+    Status {
+        job: u32,
+        invocation: gio::DBusMethodInvocation,
+    },
+    Cancel {
+        job: u32,
+        invocation: gio::DBusMethodInvocation,
+    },
+}
+
+/// Where a job is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    /// Name used on D-Bus.
+    pub fn id(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Completed => "completed",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Exports the interface on the app's connection and passes every method call to `requests`.
+pub fn export(requests: impl Fn(Request) + 'static) -> Result<(), glib::Error> {
+    let app = main_application();
+    let (Some(connection), Some(path)) = (app.dbus_connection(), app.dbus_object_path()) else {
+        return Err(glib::Error::new(
+            gio::IOErrorEnum::NotConnected,
+            "The app is not registered on the session bus",
+        ));
+    };
+
+    let node = gio::DBusNodeInfo::for_xml(INTERFACE_XML)?;
+    let interface = node
+        .lookup_interface(INTERFACE_NAME)
+        .expect("The interface is part of the XML");
+    connection
+        .register_object(&path, &interface)
+        .method_call(move |_, _, _, _, method, parameters, invocation| {
+            // The argument types were already checked against the interface by GIO
+            match method {
+                "Queue" => match parameters.get::<(String, String)>() {
+                    Some((input, output)) => requests(Request::Queue {
+                        input: input.into(),
+                        output: output.into(),
+                        invocation,
+                    }),
+                    None => return_invalid_args(invocation, "Expected two paths"),
+                },
+                "GetStatus" | "Cancel" => match parameters.get::<(u32,)>() {
+                    Some((job,)) if method == "Cancel" => {
+                        requests(Request::Cancel { job, invocation })
+                    }
+                    Some((job,)) => requests(Request::Status { job, invocation }),
+                    None => return_invalid_args(invocation, "Expected a job id"),
+                },
+                _ => invocation.return_dbus_error(
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    &format!("Unknown method {}", method),
+                ),
+            }
+        })
+        .build()?;
+    Ok(())
+}
+
+/// Answers a call about a job id that does not exist.
+pub fn return_unknown_job(invocation: gio::DBusMethodInvocation, job: u32) {
+    invocation.return_dbus_error(UNKNOWN_JOB_ERROR, &format!("There is no job {}", job));
+}
+
+/// Answers a call for a job with an invalid argument, e.g. a missing input folder.
+pub fn return_invalid_args(invocation: gio::DBusMethodInvocation, message: &str) {
+    invocation.return_dbus_error(INVALID_ARGS_ERROR, message);
+}
+
+/// Tells listeners how far `job` got.
+pub fn emit_progress(job: u32, processed: usize, total: usize) {
+    emit(
+        "Progress",
+        (job, processed as u32, total as u32).to_variant(),
+    );
+}
+
+/// Tells listeners that `job` ended, either way.
+pub fn emit_job_finished(job: u32, state: JobState, converted: usize, failed: usize) {
+    emit(
+        "JobFinished",
+        (job, state.id(), converted as u32, failed as u32).to_variant(),
+    );
+}
+
+fn emit(signal: &str, parameters: glib::Variant) {
+    let app = main_application();
+    let (Some(connection), Some(path)) = (app.dbus_connection(), app.dbus_object_path()) else {
+        return;
+    };
+    if let Err(e) = connection.emit_signal(None, &path, INTERFACE_NAME, signal, Some(&parameters)) {
+        warn!("Unable to emit the D-Bus signal {}: {}", signal, e);
+    }
+}
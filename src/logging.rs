@@ -0,0 +1,111 @@
+//! Logging to stderr and to a daily rotated file in the XDG state directory.
+//!
+//! The level is taken from `RUST_LOG` (e.g. `RUST_LOG=heic2jpg=debug`) and defaults to INFO.
+//! The command line prints its own progress, so it only shows warnings on stderr by default.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::APP_ID;
+use relm4::gtk::glib;
+
+const LOG_FILE_PREFIX: &str = "heic2jpg";
+const LOG_FILE_SUFFIX: &str = "log";
+/// Number of daily log files kept before the oldest one is deleted.
+const MAX_LOG_FILES: usize = 7;
+
+/// Folder the log files are written to.
+pub fn log_dir() -> PathBuf {
+    glib::user_state_dir().join(APP_ID).join("logs")
+}
+
+/// The log file currently written to, if there is one.
+pub fn current_log_file() -> Option<PathBuf> {
+    std::fs::read_dir(log_dir())
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(LOG_FILE_PREFIX) && name.ends_with(LOG_FILE_SUFFIX)
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+/// Reads at most the last `max_bytes` of the current log file, starting at a whole line.
+///
+/// The flag is set if earlier lines were left out.
+pub fn read_log_tail(max_bytes: u64) -> io::Result<Option<(String, bool)>> {
+    let Some(path) = current_log_file() else {
+        return Ok(None);
+    };
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let truncated = length > max_bytes;
+    if truncated {
+        file.seek(SeekFrom::Start(length - max_bytes))?;
+    }
+
+    let mut log = Vec::new();
+    file.take(max_bytes).read_to_end(&mut log)?;
+    if truncated {
+        // The first line most likely starts in the middle
+        let line_start = log.iter().position(|b| *b == b'\n').map_or(0, |i| i + 1);
+        log.drain(..line_start);
+    }
+    Ok(Some((
+        String::from_utf8_lossy(&log).into_owned(),
+        truncated,
+    )))
+}
+
+/// Sets up the global subscriber. Logs only to stderr if the log file cannot be created.
+///
+/// `stderr_level` applies to stderr unless `RUST_LOG` is set, the file always defaults to INFO.
+pub fn init(stderr_level: LevelFilter) {
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::FULL)
+        .with_writer(std::io::stderr)
+        .with_filter(env_filter(stderr_level));
+
+    let file = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir());
+    let (file, file_error) = match file {
+        Ok(appender) => {
+            let layer = tracing_subscriber::fmt::layer()
+                .with_span_events(FmtSpan::FULL)
+                .with_ansi(false)
+                .with_writer(appender)
+                .with_filter(env_filter(LevelFilter::INFO));
+            (Some(layer), None)
+        }
+        Err(e) => (None, Some(e)),
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .init();
+
+    if let Some(e) = file_error {
+        tracing::warn!("Unable to write logs to {:?}: {}", log_dir(), e);
+    }
+}
+
+/// Filter from `RUST_LOG`, or `default` if it is not set.
+fn env_filter(default: LevelFilter) -> EnvFilter {
+    EnvFilter::builder()
+        .with_default_directive(default.into())
+        .from_env_lossy()
+}
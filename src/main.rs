@@ -1,6 +1,9 @@
 #[rustfmt::skip]
 mod config;
 mod app;
+mod cli;
+mod dbus;
+mod logging;
 mod modals;
 mod pages;
 mod workers;
@@ -9,12 +12,12 @@ use config::{APP_ID, GETTEXT_PACKAGE, LOCALEDIR, RESOURCES_FILE};
 use gettextrs::LocaleCategory;
 use gtk::prelude::ApplicationExt;
 use gtk::{gio, glib};
-use magick_rust::magick_wand_genesis;
+use heic2jpg_core::{backend, decode_helper};
 use relm4::{
     actions::{AccelsPlus, RelmAction, RelmActionGroup},
     gtk, main_application, RelmApp,
 };
-use std::sync::Once;
+use tracing::level_filters::LevelFilter;
 
 use app::App;
 use relm4::set_global_css;
@@ -22,19 +25,30 @@ use relm4::set_global_css;
 relm4::new_action_group!(AppActionGroup, "app");
 relm4::new_stateless_action!(QuitAction, AppActionGroup, "quit");
 
-static START_IMAGICK: Once = Once::new();
-
 fn main() {
-    START_IMAGICK.call_once(|| {
-        magick_wand_genesis();
-    });
+    // Convert a single file and exit if started as a decode helper by the conversion worker
+    let args: Vec<_> = std::env::args_os().skip(1).collect();
+    if args
+        .first()
+        .is_some_and(|arg| arg == decode_helper::HELPER_ARG)
+    {
+        // The helper gets its resource limits as arguments, the settings may not be installed
+        logging::init(LevelFilter::WARN);
+        std::process::exit(decode_helper::run(args.into_iter().skip(1)));
+    }
+
+    // Run without GTK if a subcommand like `convert` was given
+    if cli::is_cli(&args) {
+        logging::init(LevelFilter::WARN);
+        std::process::exit(cli::run(std::env::args_os()));
+    }
+
     gtk::init().unwrap();
 
     // Enable logging
-    tracing_subscriber::fmt()
-        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::FULL)
-        .with_max_level(tracing::Level::INFO)
-        .init();
+    logging::init(LevelFilter::INFO);
+
+    backend::apply_resource_limits(&app::resource_limits());
 
     // setup gettext
     gettextrs::setlocale(LocaleCategory::LcAll, "");
@@ -50,6 +64,8 @@ fn main() {
 
     let app = main_application();
     app.set_application_id(Some(APP_ID));
+    // Files from "Open With…" or the command line are handled by `App`
+    app.set_flags(gio::ApplicationFlags::HANDLES_OPEN);
     app.set_resource_base_path(Some("/dev/nordgedanken/heic2jpg/"));
 
     let mut actions = RelmActionGroup::<AppActionGroup>::new();
@@ -64,6 +80,7 @@ fn main() {
     actions.register_for_main_application();
 
     app.set_accelerators_for_action::<QuitAction>(&["<Control>q"]);
+    app::connect_startup_open(&app);
 
     let app = RelmApp::from_app(app);
 
@@ -74,5 +91,11 @@ fn main() {
         )
         .unwrap();
     set_global_css(&glib::GString::from_utf8_checked(data.to_vec()).unwrap());
-    app.visible_on_activate(false).run::<App>(());
+    // GTK parses the arguments itself to emit `open` for the given files
+    let args = std::env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    app.visible_on_activate(false)
+        .with_args(args)
+        .run::<App>(());
 }
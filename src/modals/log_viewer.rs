@@ -0,0 +1,135 @@
+use gettextrs::gettext;
+use relm4::adw::prelude::*;
+use relm4::{adw, gtk, Component, ComponentParts, ComponentSender};
+
+use crate::logging;
+use gtk::gio;
+
+/// Only the end of the log is shown, the log folder has the rest.
+const MAX_SHOWN_BYTES: u64 = 256 * 1024;
+
+/// Shows the end of the current log file so it can be copied into bug reports.
+pub struct LogWindow {
+    /// `None` while the log is being read.
+    log: Option<String>,
+}
+
+pub struct LogWindowWidgets {
+    buffer: gtk::TextBuffer,
+}
+
+impl Component for LogWindow {
+    type Init = ();
+    type Widgets = LogWindowWidgets;
+    type Input = ();
+    type Output = ();
+    type CommandOutput = String;
+    type Root = adw::Window;
+
+    fn init_root() -> Self::Root {
+        adw::Window::builder()
+            .title(gettext("Log"))
+            .default_width(700)
+            .default_height(500)
+            .hide_on_close(true)
+            .build()
+    }
+
+    fn init(
+        _: Self::Init,
+        root: Self::Root,
+        _sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = Self { log: None };
+
+        let text_view = gtk::TextView::builder()
+            .editable(false)
+            .monospace(true)
+            .wrap_mode(gtk::WrapMode::WordChar)
+            .top_margin(12)
+            .bottom_margin(12)
+            .left_margin(12)
+            .right_margin(12)
+            .build();
+        let buffer = text_view.buffer();
+        let scrolled = gtk::ScrolledWindow::builder()
+            .child(&text_view)
+            .vexpand(true)
+            .build();
+
+        let copy_button = gtk::Button::builder()
+            .icon_name("edit-copy-symbolic")
+            .tooltip_text(gettext("Copy Log"))
+            .build();
+        copy_button.connect_clicked({
+            let buffer = buffer.clone();
+            move |button| {
+                let (start, end) = buffer.bounds();
+                button
+                    .clipboard()
+                    .set_text(&buffer.text(&start, &end, false));
+            }
+        });
+
+        let folder_button = gtk::Button::builder()
+            .icon_name("folder-open-symbolic")
+            .tooltip_text(gettext("Open Log Folder"))
+            .build();
+        folder_button.connect_clicked(|button| {
+            let folder = gio::File::for_path(logging::log_dir());
+            let window = button.root().and_downcast::<gtk::Window>();
+            gtk::FileLauncher::new(Some(&folder)).launch(
+                window.as_ref(),
+                None::<&gio::Cancellable>,
+                |result| {
+                    if let Err(e) = result {
+                        tracing::warn!("Unable to open the log folder: {}", e);
+                    }
+                },
+            );
+        });
+
+        let header_bar = adw::HeaderBar::new();
+        header_bar.pack_start(&copy_button);
+        header_bar.pack_start(&folder_button);
+
+        let toolbar_view = adw::ToolbarView::new();
+        toolbar_view.add_top_bar(&header_bar);
+        toolbar_view.set_content(Some(&scrolled));
+        root.set_content(Some(&toolbar_view));
+
+        let widgets = LogWindowWidgets { buffer };
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, _: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        // Reload on every opening to show what was logged in the meantime
+        self.log = None;
+        sender.spawn_oneshot_command(|| match logging::read_log_tail(MAX_SHOWN_BYTES) {
+            Ok(Some((log, false))) => log,
+            Ok(Some((log, true))) => format!(
+                "{}\n\n{}",
+                gettext("Earlier lines are left out, the log folder has the full log."),
+                log
+            ),
+            Ok(None) => gettext("Nothing has been logged yet."),
+            Err(e) => gettext("Unable to read the log: {}").replace("{}", &e.to_string()),
+        });
+        root.present();
+    }
+
+    fn update_cmd(
+        &mut self,
+        log: Self::CommandOutput,
+        _sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        self.log = Some(log);
+    }
+
+    fn update_view(&self, widgets: &mut Self::Widgets, _sender: ComponentSender<Self>) {
+        let log = self.log.clone().unwrap_or_else(|| gettext("Loading…"));
+        widgets.buffer.set_text(&log);
+    }
+}
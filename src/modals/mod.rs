@@ -1 +1,3 @@
 pub mod about;
+pub mod log_viewer;
+pub mod preferences;
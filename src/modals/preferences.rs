@@ -0,0 +1,422 @@
+use gettextrs::gettext;
+use relm4::adw::prelude::*;
+use relm4::{adw, gtk, ComponentParts, ComponentSender, SimpleComponent};
+
+use crate::config::APP_ID;
+use gtk::gio;
+use heic2jpg_core::backend::{BackendKind, FallbackDecoder};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct PreferencesDialog {}
+
+impl SimpleComponent for PreferencesDialog {
+    type Init = ();
+    type Widgets = adw::PreferencesWindow;
+    type Input = ();
+    type Output = ();
+    type Root = adw::PreferencesWindow;
+
+    fn init_root() -> Self::Root {
+        adw::PreferencesWindow::builder()
+            .modal(true)
+            .search_enabled(false)
+            .hide_on_close(true)
+            .build()
+    }
+
+    fn init(
+        _: Self::Init,
+        root: Self::Root,
+        _sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = Self {};
+
+        let settings = gio::Settings::new(APP_ID);
+        let page = adw::PreferencesPage::new();
+        page.add(&output_group(&settings));
+        page.add(&discovery_group(&settings));
+        page.add(&decoding_group(&settings));
+        page.add(&writing_group(&settings));
+        page.add(&limits_group(&settings));
+        root.add(&page);
+
+        let widgets = root.clone();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update_view(&self, dialog: &mut Self::Widgets, _sender: ComponentSender<Self>) {
+        dialog.present();
+    }
+}
+
+fn output_group(settings: &gio::Settings) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title(gettext("JPEG Output"))
+        .build();
+
+    let quality_row = adw::SpinRow::with_range(1.0, 100.0, 1.0);
+    quality_row.set_title(&gettext("Quality"));
+    quality_row.set_subtitle(&gettext(
+        "Higher values give better images and larger files",
+    ));
+    settings.bind("jpeg-quality", &quality_row, "value").build();
+    settings
+        .bind("target-size-enabled", &quality_row, "sensitive")
+        .invert_boolean()
+        .build();
+    group.add(&quality_row);
+
+    let target_size_row = adw::SwitchRow::builder()
+        .title(gettext("Limit File Size"))
+        .subtitle(gettext(
+            "Use the highest quality that keeps each file below the target size",
+        ))
+        .build();
+    settings
+        .bind("target-size-enabled", &target_size_row, "active")
+        .build();
+    group.add(&target_size_row);
+
+    let size_row = adw::SpinRow::with_range(10.0, 100000.0, 10.0);
+    size_row.set_title(&gettext("Target Size (kB)"));
+    settings.bind("target-size-kb", &size_row, "value").build();
+    settings
+        .bind("target-size-enabled", &size_row, "sensitive")
+        .build();
+    group.add(&size_row);
+
+    let downscale_row = adw::SwitchRow::builder()
+        .title(gettext("Downscale if Needed"))
+        .subtitle(gettext(
+            "Reduce the image dimensions instead of using a very low quality",
+        ))
+        .build();
+    settings
+        .bind("target-size-downscale", &downscale_row, "active")
+        .build();
+    settings
+        .bind("target-size-enabled", &downscale_row, "sensitive")
+        .build();
+    group.add(&downscale_row);
+
+    group
+}
+
+fn discovery_group(settings: &gio::Settings) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title(gettext("Finding Files"))
+        .description(gettext(
+            "Patterns are matched against the path inside the input folder, e.g. “**/Camera Roll/*”.",
+        ))
+        .build();
+
+    group.add(&pattern_list(
+        settings,
+        "discovery-include",
+        gettext("Only Include"),
+    ));
+    group.add(&pattern_list(
+        settings,
+        "discovery-exclude",
+        gettext("Exclude"),
+    ));
+
+    let depth_row = adw::SpinRow::with_range(0.0, 1000.0, 1.0);
+    depth_row.set_title(&gettext("Folder Depth"));
+    depth_row.set_subtitle(&gettext(
+        "0 searches all subfolders, 1 only the selected folder",
+    ));
+    settings
+        .bind("discovery-max-depth", &depth_row, "value")
+        .build();
+    group.add(&depth_row);
+
+    for (key, title) in [
+        (
+            "discovery-skip-hidden",
+            gettext("Skip Hidden Files and Folders"),
+        ),
+        (
+            "discovery-follow-symlinks",
+            gettext("Follow Symbolic Links"),
+        ),
+        (
+            "discovery-same-file-system",
+            gettext("Stay on the Same File System"),
+        ),
+    ] {
+        let row = adw::SwitchRow::builder().title(title).build();
+        settings.bind(key, &row, "active").build();
+        group.add(&row);
+    }
+
+    group
+}
+
+fn decoding_group(settings: &gio::Settings) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title(gettext("Decoding"))
+        .build();
+
+    let backends: Vec<_> = BackendKind::ALL
+        .iter()
+        .map(|kind| (kind.id(), kind.name().to_string()))
+        .collect();
+    let backend_row = adw::ComboRow::builder()
+        .title(gettext("Image Backend"))
+        .subtitle(gettext("Library used to read the HEIC files"))
+        // Nothing to choose from if only one backend was compiled in
+        .visible(backends.len() > 1)
+        .build();
+    bind_choice(settings, "backend", &backend_row, backends);
+    group.add(&backend_row);
+
+    let mut fallbacks = vec![("none", gettext("None"))];
+    fallbacks.extend(
+        FallbackDecoder::all()
+            .into_iter()
+            .map(|fallback| (fallback.id(), fallback.name().to_string())),
+    );
+    let fallback_row = adw::ComboRow::builder()
+        .title(gettext("Fallback Decoder"))
+        .subtitle(gettext(
+            "Used when the image backend cannot read a file. heif-convert has to be installed separately.",
+        ))
+        .build();
+    bind_choice(settings, "fallback-decoder", &fallback_row, fallbacks);
+    group.add(&fallback_row);
+
+    let isolate_row = adw::SwitchRow::builder()
+        .title(gettext("Decode in a Separate Process"))
+        .subtitle(gettext(
+            "A file that crashes the decoder is skipped instead of closing the app. Slightly slower. Always on while a timeout is set.",
+        ))
+        .build();
+    settings
+        .bind("isolate-decoding", &isolate_row, "active")
+        .build();
+    group.add(&isolate_row);
+
+    let sandbox_row = adw::SwitchRow::builder()
+        .title(gettext("Sandbox the Decoder"))
+        .subtitle(gettext(
+            "Only allow the decoder process to read the image and write the result. Turn off for debugging.",
+        ))
+        .build();
+    settings
+        .bind("sandbox-decoding", &sandbox_row, "active")
+        .build();
+    group.add(&sandbox_row);
+
+    group
+}
+
+fn writing_group(settings: &gio::Settings) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title(gettext("Writing Files"))
+        .build();
+
+    let sync_row = adw::SwitchRow::builder()
+        .title(gettext("Flush to Disk"))
+        .subtitle(gettext(
+            "Wait until every file is stored on the drive. Slower, but safer with USB sticks.",
+        ))
+        .build();
+    settings.bind("sync-output", &sync_row, "active").build();
+    group.add(&sync_row);
+
+    let verify_row = adw::SwitchRow::builder()
+        .title(gettext("Verify Written Files"))
+        .subtitle(gettext(
+            "Read every JPEG back after writing it and mark damaged files as failed",
+        ))
+        .build();
+    settings
+        .bind("verify-output", &verify_row, "active")
+        .build();
+    group.add(&verify_row);
+
+    let metric_row = adw::ComboRow::builder()
+        .title(gettext("Compare to Original"))
+        .build();
+    bind_choice(
+        settings,
+        "verify-metric",
+        &metric_row,
+        vec![
+            ("none", gettext("Only Check Dimensions")),
+            ("psnr", "PSNR".to_string()),
+            ("ssim", "SSIM".to_string()),
+        ],
+    );
+    settings
+        .bind("verify-output", &metric_row, "sensitive")
+        .build();
+    group.add(&metric_row);
+
+    let psnr_row = adw::SpinRow::with_range(10.0, 100.0, 0.5);
+    psnr_row.set_title(&gettext("Minimum PSNR (dB)"));
+    psnr_row.set_digits(1);
+    settings.bind("verify-min-psnr", &psnr_row, "value").build();
+    group.add(&psnr_row);
+
+    let ssim_row = adw::SpinRow::with_range(0.0, 1.0, 0.01);
+    ssim_row.set_title(&gettext("Minimum SSIM"));
+    ssim_row.set_digits(2);
+    settings.bind("verify-min-ssim", &ssim_row, "value").build();
+    group.add(&ssim_row);
+
+    for row in [&psnr_row, &ssim_row] {
+        settings.bind("verify-output", row, "sensitive").build();
+    }
+
+    // Only show the threshold of the selected comparison
+    let update_thresholds = move |metric_row: &adw::ComboRow| {
+        psnr_row.set_visible(metric_row.selected() == 1);
+        ssim_row.set_visible(metric_row.selected() == 2);
+    };
+    update_thresholds(&metric_row);
+    metric_row.connect_selected_notify(update_thresholds);
+
+    group
+}
+
+fn limits_group(settings: &gio::Settings) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title(gettext("Limits"))
+        .description(gettext(
+            "0 means no limit. Changes to the ImageMagick limits take effect after a restart.",
+        ))
+        .build();
+
+    for (key, title, max) in [
+        (
+            "magick-memory-limit-mb",
+            gettext("ImageMagick Memory (MB)"),
+            1048576.0,
+        ),
+        (
+            "magick-disk-limit-mb",
+            gettext("ImageMagick Disk Space (MB)"),
+            1048576.0,
+        ),
+        (
+            "magick-thread-limit",
+            gettext("ImageMagick Threads"),
+            1024.0,
+        ),
+        (
+            "file-timeout-seconds",
+            gettext("Timeout per File (Seconds)"),
+            86400.0,
+        ),
+    ] {
+        let row = adw::SpinRow::with_range(0.0, max, 1.0);
+        row.set_title(&title);
+        settings.bind(key, &row, "value").build();
+        group.add(&row);
+    }
+
+    group
+}
+
+/// Offers `choices` as (stored id, shown name) pairs and keeps the selection in sync with `key`.
+fn bind_choice(
+    settings: &gio::Settings,
+    key: &'static str,
+    row: &adw::ComboRow,
+    choices: Vec<(&'static str, String)>,
+) {
+    let names: Vec<&str> = choices.iter().map(|(_, name)| name.as_str()).collect();
+    row.set_model(Some(&gtk::StringList::new(&names)));
+
+    let current = settings.string(key);
+    let position = choices.iter().position(|(id, _)| *id == current.as_str());
+    row.set_selected(position.unwrap_or(0) as u32);
+    row.connect_selected_notify({
+        let settings = settings.clone();
+        move |row| {
+            if let Some((id, _)) = choices.get(row.selected() as usize) {
+                if let Err(e) = settings.set_string(key, id) {
+                    tracing::warn!("Unable to store {}: {}", key, e);
+                }
+            }
+        }
+    });
+}
+
+/// Lists the glob patterns stored in `key` one per row, so patterns may contain spaces.
+///
+/// Patterns are added with an entry row and removed with a button on their row.
+fn pattern_list(settings: &gio::Settings, key: &'static str, title: String) -> adw::ExpanderRow {
+    let expander = adw::ExpanderRow::builder().title(title).build();
+    let add_row = adw::EntryRow::builder()
+        .title(gettext("Add Pattern"))
+        .show_apply_button(true)
+        .build();
+    expander.add_row(&add_row);
+    add_row.connect_apply({
+        let settings = settings.clone();
+        move |row| {
+            let text = row.text();
+            let pattern = text.trim();
+            if pattern.is_empty() {
+                return;
+            }
+            let mut patterns: Vec<String> =
+                settings.strv(key).iter().map(|p| p.to_string()).collect();
+            patterns.push(pattern.to_string());
+            store_patterns(&settings, key, &patterns);
+            row.set_text("");
+        }
+    });
+
+    let rows = Rc::new(RefCell::new(Vec::new()));
+    let show_patterns = {
+        let expander = expander.clone();
+        move |settings: &gio::Settings| {
+            for row in rows.borrow_mut().drain(..) {
+                expander.remove(&row);
+            }
+            let patterns: Vec<String> = settings.strv(key).iter().map(|p| p.to_string()).collect();
+            expander.set_subtitle(&patterns.join(", "));
+            for (index, pattern) in patterns.iter().enumerate() {
+                let row = adw::ActionRow::builder()
+                    .title(pattern.as_str())
+                    // Patterns are not markup
+                    .use_markup(false)
+                    .build();
+                let remove_button = gtk::Button::builder()
+                    .icon_name("user-trash-symbolic")
+                    .tooltip_text(gettext("Remove"))
+                    .valign(gtk::Align::Center)
+                    .css_classes(["flat"])
+                    .build();
+                remove_button.connect_clicked({
+                    let settings = settings.clone();
+                    let mut patterns = patterns.clone();
+                    move |_| {
+                        patterns.remove(index);
+                        store_patterns(&settings, key, &patterns);
+                    }
+                });
+                row.add_suffix(&remove_button);
+                expander.add_row(&row);
+                rows.borrow_mut().push(row);
+            }
+        }
+    };
+    show_patterns(settings);
+    settings.connect_changed(Some(key), move |settings, _| show_patterns(settings));
+
+    expander
+}
+
+fn store_patterns(settings: &gio::Settings, key: &str, patterns: &[String]) {
+    let patterns: Vec<&str> = patterns.iter().map(String::as_str).collect();
+    if let Err(e) = settings.set_strv(key, patterns.as_slice()) {
+        tracing::warn!("Unable to store {}: {}", key, e);
+    }
+}
@@ -3,12 +3,40 @@ use relm4::{
     adw,
     gtk::{
         self,
-        prelude::{BoxExt, ButtonExt, OrientableExt, WidgetExt},
+        prelude::{BoxExt, ButtonExt, FileFilterExt, OrientableExt, WidgetExt},
     },
-    ComponentParts, ComponentSender, SimpleComponent,
+    Component, ComponentController, ComponentParts, ComponentSender, Controller, SimpleComponent,
 };
+use relm4_components::save_dialog::*;
+use std::path::PathBuf;
+use tracing::{error, info};
 
-pub struct FinishedPage;
+use heic2jpg_core::{ConversionReport, ReportFormat};
+
+pub struct FinishedPage {
+    save_dialog: Controller<SaveDialog>,
+    warnings: Vec<String>,
+    failures: Vec<String>,
+    report: Option<ConversionReport>,
+    /// Report of an earlier job that is being saved instead of the shown one.
+    saving: Option<ConversionReport>,
+}
+
+#[derive(Debug)]
+pub enum FinishedPageInput {
+    /// Something the user should know about, e.g. a skipped symlink loop.
+    AddWarning(String),
+    /// A file that could not be converted.
+    AddFailure(String),
+    SetReport(ConversionReport),
+    SaveReportRequest,
+    /// Asks where to save the report of an earlier job from the queue.
+    SaveJobReport(ConversionReport),
+    /// Writes the report, the format is picked from the file extension.
+    SaveReport(PathBuf),
+    Ignore,
+    Reset,
+}
 
 #[derive(Debug)]
 pub enum FinishedPageMsg {
@@ -19,7 +47,7 @@ pub enum FinishedPageMsg {
 #[relm4::component(pub)]
 impl SimpleComponent for FinishedPage {
     type Init = ();
-    type Input = ();
+    type Input = FinishedPageInput;
     type Output = FinishedPageMsg;
 
     view! {
@@ -27,25 +55,60 @@ impl SimpleComponent for FinishedPage {
             set_hexpand: true,
             set_vexpand: true,
             set_title: &gettext("Conversion Complete"),
-            set_description: Some(&gettext("The conversion was successful")),
-            set_icon_name: Some("test-pass"),
+            #[watch]
+            set_description: Some(&model.description()),
+            #[watch]
+            set_icon_name: Some(if model.failures.is_empty() { "test-pass" } else { "dialog-warning-symbolic" }),
             gtk::Box {
                 set_halign: gtk::Align::Center,
-                set_orientation: gtk::Orientation::Horizontal,
+                set_orientation: gtk::Orientation::Vertical,
                 set_spacing: 24,
 
-                gtk::Button {
-                    set_label: "Close",
-                    add_css_class: "suggested-action",
-                    add_css_class: "pill",
-                    connect_clicked[sender] => move |_| {
-                        let _ = sender.output(FinishedPageMsg::Quit);
-                    }
+                gtk::Label {
+                    set_wrap: true,
+                    set_justify: gtk::Justification::Center,
+                    #[watch]
+                    set_visible: !model.failures.is_empty(),
+                    #[watch]
+                    set_label: &model.failures.join("\n"),
                 },
-                gtk::Button {
-                    set_label: "Restart",
-                    connect_clicked[sender] => move |_| {
-                        let _ = sender.output(FinishedPageMsg::StartOver);
+
+                gtk::Label {
+                    set_wrap: true,
+                    set_justify: gtk::Justification::Center,
+                    add_css_class: "dim-label",
+                    #[watch]
+                    set_visible: !model.warnings.is_empty(),
+                    #[watch]
+                    set_label: &model.warnings.join("\n"),
+                },
+
+                gtk::Box {
+                    set_halign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_spacing: 24,
+
+                    gtk::Button {
+                        set_label: "Close",
+                        add_css_class: "suggested-action",
+                        add_css_class: "pill",
+                        connect_clicked[sender] => move |_| {
+                            let _ = sender.output(FinishedPageMsg::Quit);
+                        }
+                    },
+                    gtk::Button {
+                        set_label: "Restart",
+                        connect_clicked[sender] => move |_| {
+                            let _ = sender.output(FinishedPageMsg::StartOver);
+                        }
+                    },
+                    gtk::Button {
+                        set_label: &gettext("Save Report…"),
+                        #[watch]
+                        set_visible: model.report.is_some(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(FinishedPageInput::SaveReportRequest);
+                        }
                     }
                 }
             }
@@ -57,9 +120,92 @@ impl SimpleComponent for FinishedPage {
         root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        let model = Self {};
+        let filters = ReportFormat::ALL
+            .iter()
+            .map(|format| {
+                let filter = gtk::FileFilter::new();
+                filter.set_name(Some(&format.extension().to_uppercase()));
+                filter.add_suffix(format.extension());
+                filter
+            })
+            .collect();
+        let dialog_settings = SaveDialogSettings {
+            filters,
+            ..Default::default()
+        };
+        let save_dialog = SaveDialog::builder()
+            .transient_for_native(&root)
+            .launch(dialog_settings)
+            .forward(sender.input_sender(), |response| match response {
+                SaveDialogResponse::Accept(path) => FinishedPageInput::SaveReport(path),
+                SaveDialogResponse::Cancel => FinishedPageInput::Ignore,
+            });
+
+        let model = Self {
+            save_dialog,
+            warnings: Vec::new(),
+            failures: Vec::new(),
+            report: None,
+            saving: None,
+        };
 
         let widgets = view_output!();
         ComponentParts { model, widgets }
     }
+
+    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+        match message {
+            FinishedPageInput::AddWarning(warning) => self.warnings.push(warning),
+            FinishedPageInput::AddFailure(failure) => self.failures.push(failure),
+            FinishedPageInput::SetReport(report) => self.report = Some(report),
+            FinishedPageInput::SaveReportRequest => self.request_save(),
+            FinishedPageInput::SaveJobReport(report) => {
+                self.saving = Some(report);
+                self.request_save();
+            }
+            FinishedPageInput::SaveReport(path) => self.save_report(path),
+            FinishedPageInput::Ignore => self.saving = None,
+            FinishedPageInput::Reset => {
+                self.warnings.clear();
+                self.failures.clear();
+                self.report = None;
+            }
+        }
+    }
+}
+
+impl FinishedPage {
+    fn request_save(&self) {
+        self.save_dialog.emit(SaveDialogMsg::SaveAs(format!(
+            "conversion-report.{}",
+            ReportFormat::Html.extension()
+        )));
+    }
+
+    fn save_report(&mut self, mut path: PathBuf) {
+        let saving = self.saving.take();
+        let Some(report) = saving.as_ref().or(self.report.as_ref()) else {
+            return;
+        };
+        let format = ReportFormat::from_path(&path).unwrap_or_else(|| {
+            path.as_mut_os_string().push(".html");
+            ReportFormat::Html
+        });
+
+        info!("Saving {:?} report to {:?}", format, path);
+        if let Err(e) = std::fs::write(&path, report.render(format)) {
+            error!("Unable to save the report to {:?}: {}", path, e);
+            self.warnings
+                .push(gettext("The report could not be saved: {}").replace("{}", &e.to_string()));
+        }
+    }
+
+    fn description(&self) -> String {
+        if self.failures.is_empty() {
+            gettext("The conversion was successful")
+        } else {
+            gettext("{} files could not be converted")
+                .replace("{}", &self.failures.len().to_string())
+        }
+    }
 }
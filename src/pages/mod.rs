@@ -1,3 +1,4 @@
 pub mod finished_page;
 pub mod progressing_page;
+pub mod queue_list;
 pub mod select_folder;
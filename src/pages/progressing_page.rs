@@ -2,21 +2,30 @@ use gettextrs::gettext;
 use relm4::{
     adw,
     gtk::{
-        self,
+        self, glib, pango,
         prelude::{BoxExt, OrientableExt, WidgetExt},
     },
     ComponentParts, ComponentSender, SimpleComponent,
 };
+use std::path::PathBuf;
+use std::time::Duration;
+
+use heic2jpg_core::ProgressInfo;
 
 pub struct ProgressingPage {
     file_count: usize,
-    progress: f64,
+    scanning: bool,
+    current_file: Option<PathBuf>,
+    progress: Option<ProgressInfo>,
 }
 
 #[derive(Debug)]
 pub enum ProgressingPageMsg {
     SetFileCount(usize),
-    SetProgress(f64),
+    SetScanning(bool),
+    SetCurrentFile(PathBuf),
+    SetProgress(ProgressInfo),
+    Reset,
 }
 
 #[relm4::component(pub)]
@@ -36,23 +45,51 @@ impl SimpleComponent for ProgressingPage {
                 set_halign: gtk::Align::Center,
                 set_orientation: gtk::Orientation::Vertical,
                 set_spacing: 8,
+                set_width_request: 300,
 
+                gtk::Label {
+                    set_xalign: 0.5,
+                    add_css_class: "dim-label",
+                    #[watch]
+                    set_visible: model.scanning,
+                    #[watch]
+                    set_label: &gettext("Scanning… {} found")
+                        .replace("{}", &model.file_count.to_string()),
+                },
+                gtk::Label {
+                    set_xalign: 0.5,
+                    set_ellipsize: pango::EllipsizeMode::Middle,
+                    #[watch]
+                    set_visible: model.current_file.is_some(),
+                    #[watch]
+                    set_label: &model
+                        .current_file
+                        .as_ref()
+                        .and_then(|file| file.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                },
                 gtk::Label {
                     set_xalign: 0.5,
                     #[watch]
                     set_visible: model.file_count > 0,
                     #[watch]
-                    set_label: &format!(
-                        "{} / {}",
-                        model.file_count as u32 * model.progress as u32,
-                        model.file_count
-                    ),
+                    set_label: &format!("{} / {}", model.processed(), model.file_count),
                 },
                 gtk::ProgressBar {
                     set_hexpand: true,
                     #[watch]
-                    set_fraction: model.progress,
-                }
+                    set_fraction: model.fraction(),
+                },
+                gtk::Label {
+                    set_xalign: 0.5,
+                    add_css_class: "dim-label",
+                    add_css_class: "numeric",
+                    #[watch]
+                    set_visible: model.progress.is_some(),
+                    #[watch]
+                    set_label: &model.statistics(),
+                },
             }
         }
     }
@@ -64,7 +101,9 @@ impl SimpleComponent for ProgressingPage {
     ) -> ComponentParts<Self> {
         let model = Self {
             file_count: 0,
-            progress: 0.0,
+            scanning: false,
+            current_file: None,
+            progress: None,
         };
 
         let widgets = view_output!();
@@ -76,9 +115,65 @@ impl SimpleComponent for ProgressingPage {
             ProgressingPageMsg::SetFileCount(file_count) => {
                 self.file_count = file_count;
             }
+            ProgressingPageMsg::SetScanning(scanning) => {
+                self.scanning = scanning;
+            }
+            ProgressingPageMsg::SetCurrentFile(file) => {
+                self.current_file = Some(file);
+            }
             ProgressingPageMsg::SetProgress(progress) => {
-                self.progress = progress;
+                self.file_count = self.file_count.max(progress.total);
+                self.progress = Some(progress);
+            }
+            ProgressingPageMsg::Reset => {
+                self.file_count = 0;
+                self.scanning = false;
+                self.current_file = None;
+                self.progress = None;
             }
         }
     }
 }
+
+impl ProgressingPage {
+    fn processed(&self) -> usize {
+        self.progress
+            .as_ref()
+            .map_or(0, |progress| progress.processed)
+    }
+
+    fn fraction(&self) -> f64 {
+        if self.file_count == 0 {
+            return 0.0;
+        }
+        self.processed() as f64 / self.file_count as f64
+    }
+
+    /// Throughput, processed bytes, elapsed time and the ETA as a single line.
+    fn statistics(&self) -> String {
+        let Some(progress) = &self.progress else {
+            return String::new();
+        };
+
+        let mut parts = vec![
+            gettext("{} files/s").replace("{}", &format!("{:.1}", progress.files_per_second)),
+            gettext("{} read").replace("{}", &glib::format_size(progress.bytes_read)),
+            gettext("{} elapsed").replace("{}", &format_duration(progress.elapsed)),
+        ];
+        if let Some(eta) = progress.eta {
+            parts.push(gettext("about {} left").replace("{}", &format_duration(eta)));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// Formats a duration as `m:ss` or `h:mm:ss`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
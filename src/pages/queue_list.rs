@@ -0,0 +1,248 @@
+use gettextrs::gettext;
+use relm4::adw::prelude::*;
+use relm4::factory::{DynamicIndex, FactoryComponent, FactorySender, FactoryVecDeque};
+use relm4::{adw, gtk, ComponentParts, ComponentSender, SimpleComponent};
+use std::path::PathBuf;
+
+/// A job that is running, waiting for the running one or ended.
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub id: u32,
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub state: EntryState,
+}
+
+#[derive(Debug, Clone)]
+pub enum EntryState {
+    Queued,
+    Running,
+    /// How the job went, e.g. `3 converted, 1 failed`, the failures and warnings and whether
+    /// there is a report to save.
+    Finished {
+        summary: String,
+        details: Vec<String>,
+        has_report: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum QueueListMsg {
+    /// Replaces the shown jobs, the queued ones in the order they will run.
+    SetJobs(Vec<QueueEntry>),
+}
+
+#[derive(Debug)]
+pub enum QueueListOut {
+    AddJob,
+    MoveUp(u32),
+    MoveDown(u32),
+    Remove(u32),
+    /// Stops the running job.
+    Cancel(u32),
+    SaveReport(u32),
+}
+
+/// The running, queued and recently ended jobs. Queued ones can be reordered and removed while
+/// another one runs.
+pub struct QueueList {
+    jobs: FactoryVecDeque<QueueRow>,
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for QueueList {
+    type Init = ();
+    type Input = QueueListMsg;
+    type Output = QueueListOut;
+
+    view! {
+        gtk::Box {
+            set_halign: gtk::Align::Center,
+            set_orientation: gtk::Orientation::Vertical,
+            set_spacing: 12,
+            set_width_request: 360,
+            set_margin_bottom: 24,
+
+            gtk::Label {
+                set_xalign: 0.0,
+                add_css_class: "heading",
+                set_label: &gettext("Jobs"),
+                #[watch]
+                set_visible: !model.jobs.is_empty(),
+            },
+
+            #[local_ref]
+            job_list -> gtk::ListBox {
+                add_css_class: "boxed-list",
+                set_selection_mode: gtk::SelectionMode::None,
+                #[watch]
+                set_visible: !model.jobs.is_empty(),
+            },
+
+            gtk::Button {
+                set_halign: gtk::Align::Center,
+                set_label: &gettext("Add Job"),
+                add_css_class: "pill",
+                connect_clicked[sender] => move |_| {
+                    let _ = sender.output(QueueListOut::AddJob);
+                }
+            }
+        }
+    }
+
+    fn init(
+        _init: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let jobs = FactoryVecDeque::builder()
+            .launch(gtk::ListBox::default())
+            .forward(sender.output_sender(), |msg| msg);
+        let model = Self { jobs };
+
+        let job_list = model.jobs.widget();
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+        match message {
+            QueueListMsg::SetJobs(jobs) => {
+                let mut guard = self.jobs.guard();
+                guard.clear();
+                for job in jobs {
+                    guard.push_back(job);
+                }
+            }
+        }
+    }
+}
+
+struct QueueRow {
+    entry: QueueEntry,
+}
+
+impl QueueRow {
+    /// The names of the inputs, e.g. `Phone, IMG_0001.heic`.
+    fn title(&self) -> String {
+        let names: Vec<_> = self
+            .entry
+            .inputs
+            .iter()
+            .map(|input| {
+                input
+                    .file_name()
+                    .unwrap_or(input.as_os_str())
+                    .to_string_lossy()
+            })
+            .collect();
+        names.join(", ")
+    }
+
+    fn subtitle(&self) -> String {
+        let output = self.entry.output.to_string_lossy();
+        match &self.entry.state {
+            EntryState::Queued => gettext("To “{}”").replace("{}", &output),
+            EntryState::Running => gettext("Converting to “{}”").replace("{}", &output),
+            EntryState::Finished { summary, .. } => summary.clone(),
+        }
+    }
+
+    /// The failures and warnings of an ended job, one per line.
+    fn details(&self) -> String {
+        match &self.entry.state {
+            EntryState::Finished { details, .. } => details.join("\n"),
+            _ => String::new(),
+        }
+    }
+
+    fn is_queued(&self) -> bool {
+        matches!(self.entry.state, EntryState::Queued)
+    }
+}
+
+#[relm4::factory]
+impl FactoryComponent for QueueRow {
+    type Init = QueueEntry;
+    type Input = ();
+    type Output = QueueListOut;
+    type CommandOutput = ();
+    type ParentWidget = gtk::ListBox;
+
+    view! {
+        adw::ExpanderRow {
+            // Paths are not markup
+            set_use_markup: false,
+            set_title: &self.title(),
+            set_subtitle: &self.subtitle(),
+            set_enable_expansion: !self.details().is_empty(),
+            set_show_enable_switch: false,
+
+            add_row = &gtk::Label {
+                set_wrap: true,
+                set_xalign: 0.0,
+                set_selectable: true,
+                set_margin_top: 12,
+                set_margin_bottom: 12,
+                set_margin_start: 12,
+                set_margin_end: 12,
+                set_label: &self.details(),
+            },
+
+            add_suffix = &gtk::Button {
+                set_icon_name: "go-up-symbolic",
+                set_tooltip_text: Some(&gettext("Move Up")),
+                set_visible: self.is_queued(),
+                set_valign: gtk::Align::Center,
+                add_css_class: "flat",
+                connect_clicked[sender, id = self.entry.id] => move |_| {
+                    let _ = sender.output(QueueListOut::MoveUp(id));
+                }
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: "go-down-symbolic",
+                set_tooltip_text: Some(&gettext("Move Down")),
+                set_visible: self.is_queued(),
+                set_valign: gtk::Align::Center,
+                add_css_class: "flat",
+                connect_clicked[sender, id = self.entry.id] => move |_| {
+                    let _ = sender.output(QueueListOut::MoveDown(id));
+                }
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: "user-trash-symbolic",
+                set_tooltip_text: Some(&gettext("Remove")),
+                set_visible: self.is_queued(),
+                set_valign: gtk::Align::Center,
+                add_css_class: "flat",
+                connect_clicked[sender, id = self.entry.id] => move |_| {
+                    let _ = sender.output(QueueListOut::Remove(id));
+                }
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: "process-stop-symbolic",
+                set_tooltip_text: Some(&gettext("Cancel")),
+                set_visible: matches!(self.entry.state, EntryState::Running),
+                set_valign: gtk::Align::Center,
+                add_css_class: "flat",
+                connect_clicked[sender, id = self.entry.id] => move |_| {
+                    let _ = sender.output(QueueListOut::Cancel(id));
+                }
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: "document-save-symbolic",
+                set_tooltip_text: Some(&gettext("Save Report…")),
+                set_visible: matches!(self.entry.state, EntryState::Finished { has_report: true, .. }),
+                set_valign: gtk::Align::Center,
+                add_css_class: "flat",
+                connect_clicked[sender, id = self.entry.id] => move |_| {
+                    let _ = sender.output(QueueListOut::SaveReport(id));
+                }
+            },
+        }
+    }
+
+    fn init_model(entry: Self::Init, _index: &DynamicIndex, _sender: FactorySender<Self>) -> Self {
+        Self { entry }
+    }
+}
@@ -1,11 +1,14 @@
 use gettextrs::gettext;
+use gtk::gdk;
 use gtk::prelude::*;
+use heic2jpg_core::discovery;
 use relm4::{
     adw, gtk, Component, ComponentController, ComponentParts, ComponentSender, Controller,
     SimpleComponent,
 };
 use relm4_components::open_dialog::*;
 use std::path::PathBuf;
+use tracing::warn;
 
 #[derive(Debug, PartialEq)]
 pub(crate) enum InOut {
@@ -16,6 +19,8 @@ pub(crate) enum InOut {
 #[derive(Debug)]
 pub(crate) struct SelectFolder {
     open_dialog: Controller<OpenDialog>,
+    /// Picks single HEIC files instead of a folder, only used for the input.
+    files_dialog: Controller<OpenDialogMulti>,
     folder: Option<PathBuf>,
     description: String,
     button_label: String,
@@ -25,13 +30,18 @@ pub(crate) struct SelectFolder {
 #[derive(Debug)]
 pub(crate) enum SelectFolderMsg {
     OpenRequest,
+    OpenFilesRequest,
     OpenResponse(PathBuf),
+    /// Inputs picked in the files dialog or dropped onto the page.
+    InputsResponse(Vec<PathBuf>),
     Ignore,
 }
 
 #[derive(Debug)]
 pub(crate) enum SelectFolderOut {
     FolderSelected(PathBuf),
+    /// Files and folders to convert, only sent by the input page.
+    InputsSelected(Vec<PathBuf>),
     AbortLast,
 }
 
@@ -64,6 +74,16 @@ impl SimpleComponent for SelectFolder {
                     }
                 },
 
+                gtk::Button {
+                    set_visible: model.direction == InOut::Input,
+                    set_halign: gtk::Align::Center,
+                    set_label: &gettext("Select files"),
+                    add_css_class: "pill",
+                    connect_clicked[sender] => move |_| {
+                        sender.input(SelectFolderMsg::OpenFilesRequest);
+                    }
+                },
+
                 // If this is the output folder selector, add an Restart button
                 gtk::Button {
                     set_visible: model.direction == InOut::Output,
@@ -85,7 +105,7 @@ impl SimpleComponent for SelectFolder {
     ) -> ComponentParts<Self> {
         let (description, button_label) = match init {
             InOut::Input => (
-                gettext("Select the folder where the Heic files can be found"),
+                gettext("Select a folder or single Heic files, or drop files and folders here"),
                 gettext("Select input directory"),
             ),
             InOut::Output => (
@@ -107,8 +127,57 @@ impl SimpleComponent for SelectFolder {
                 OpenDialogResponse::Cancel => SelectFolderMsg::Ignore,
             });
 
+        let heic_filter = gtk::FileFilter::new();
+        heic_filter.set_name(Some(&gettext("HEIC Images")));
+        heic_filter.add_mime_type("image/heic");
+        heic_filter.add_mime_type("image/heif");
+        heic_filter.add_suffix("heic");
+        heic_filter.add_suffix("heif");
+        let files_dialog_settings = OpenDialogSettings {
+            filters: vec![heic_filter],
+            ..Default::default()
+        };
+        let files_dialog = OpenDialogMulti::builder()
+            .transient_for_native(&root)
+            .launch(files_dialog_settings)
+            .forward(sender.input_sender(), |response| match response {
+                OpenDialogResponse::Accept(paths) => SelectFolderMsg::InputsResponse(paths),
+                OpenDialogResponse::Cancel => SelectFolderMsg::Ignore,
+            });
+
+        // Besides folders, the input page takes single HEIC files as well
+        let accepts_files = init == InOut::Input;
+        add_drop_target(&root, {
+            let sender = sender.clone();
+            move |paths| {
+                let paths: Vec<_> = paths
+                    .into_iter()
+                    .filter(|path| path.is_dir() || (accepts_files && discovery::is_heic(path)))
+                    .collect();
+                if accepts_files {
+                    if paths.is_empty() {
+                        return false;
+                    }
+                    sender.input(SelectFolderMsg::InputsResponse(paths));
+                    return true;
+                }
+
+                // The output is a single folder
+                let mut folders = paths.into_iter();
+                let Some(folder) = folders.next() else {
+                    return false;
+                };
+                if folders.next().is_some() {
+                    warn!("Only the first dropped folder {:?} is used", folder);
+                }
+                sender.input(SelectFolderMsg::OpenResponse(folder));
+                true
+            }
+        });
+
         let model = Self {
             open_dialog,
+            files_dialog,
             folder: None,
             description: description.to_string(),
             button_label: button_label.to_string(),
@@ -122,11 +191,40 @@ impl SimpleComponent for SelectFolder {
     fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>) {
         match message {
             SelectFolderMsg::OpenRequest => self.open_dialog.emit(OpenDialogMsg::Open),
+            SelectFolderMsg::OpenFilesRequest => self.files_dialog.emit(OpenDialogMsg::Open),
             SelectFolderMsg::OpenResponse(path) => {
                 self.folder = Some(path.clone());
                 let _ = sender.output(SelectFolderOut::FolderSelected(path.clone()));
             }
+            SelectFolderMsg::InputsResponse(paths) => {
+                self.folder = None;
+                let _ = sender.output(SelectFolderOut::InputsSelected(paths));
+            }
             SelectFolderMsg::Ignore => {}
         }
     }
 }
+
+/// Lets files and folders be dragged onto `widget`, e.g. from the file manager.
+///
+/// `on_drop` gets the local paths and returns whether it took them. The widget is highlighted
+/// while something is dragged over it.
+pub(crate) fn add_drop_target(
+    widget: &impl IsA<gtk::Widget>,
+    on_drop: impl Fn(Vec<PathBuf>) -> bool + 'static,
+) {
+    let drop_target = gtk::DropTarget::new(gdk::FileList::static_type(), gdk::DragAction::COPY);
+    drop_target.connect_drop(move |_, value, _, _| {
+        let Ok(files) = value.get::<gdk::FileList>() else {
+            return false;
+        };
+        let paths: Vec<_> = files
+            .files()
+            .iter()
+            .filter_map(|file| file.path())
+            .collect();
+        !paths.is_empty() && on_drop(paths)
+    });
+    widget.add_controller(drop_target);
+    widget.add_css_class("drop-target");
+}